@@ -0,0 +1,126 @@
+/* Benchmarks of the per-pixel hot paths of rendering, run with `cargo bench`.
+Every function is compared with the code it replaced, at the sizes svg-render-size can be set to.
+Iconic is not a library, so the module is included directly */
+#[path = "../src/objects/pixels.rs"]
+mod pixels;
+
+use image::{imageops, ImageBuffer, Rgba, RgbaImage};
+use resvg::tiny_skia::{Color, Pixmap};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const SIZES: [u32; 3] = [512, 1024, 2048];
+const RUNS: usize = 15;
+
+fn main() {
+    for size in SIZES {
+        println!("{size}x{size}");
+        let pixmap = test_pixmap(size);
+        compare(
+            "pixmap to image",
+            || pixmap_to_image_per_pixel(black_box(pixmap.clone())),
+            || pixels::pixmap_to_rgba(black_box(pixmap.clone())),
+        );
+        let image = pixels::pixmap_to_rgba(pixmap);
+        compare(
+            "monochrome",
+            || monochrome_per_pixel(black_box(image.clone()), 128, [53, 132, 228], false),
+            || pixels::monochrome(black_box(image.clone()), 128, [53, 132, 228], false),
+        );
+        let base = RgbaImage::from_pixel(size, size, Rgba([67, 141, 230, 255]));
+        compare(
+            "overlay",
+            || {
+                let mut base = base.clone();
+                imageops::overlay(&mut base, black_box(&image), 0, 0);
+                base
+            },
+            || {
+                let mut base = base.clone();
+                pixels::overlay_linear(&mut base, black_box(&image), 0, 0);
+                base
+            },
+        );
+    }
+}
+
+fn compare<A, B, T, U>(name: &str, before: A, after: B)
+where
+    A: Fn() -> T,
+    B: Fn() -> U,
+{
+    let before = median(before);
+    let after = median(after);
+    println!(
+        "  {:<16} before: {:>9.2?}  after: {:>9.2?}  speedup: {:.1}x",
+        name,
+        before,
+        after,
+        before.as_secs_f64() / after.as_secs_f64()
+    );
+}
+
+fn median<F, T>(function: F) -> Duration
+where
+    F: Fn() -> T,
+{
+    let mut times: Vec<Duration> = (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            black_box(function());
+            start.elapsed()
+        })
+        .collect();
+    times.sort();
+    times[RUNS / 2]
+}
+
+// A gradient with transparent parts, so both branches of the monochrome threshold are taken
+fn test_pixmap(size: u32) -> Pixmap {
+    let mut pixmap = Pixmap::new(size, size).unwrap();
+    pixmap.fill(Color::TRANSPARENT);
+    for (index, pixel) in pixmap.data_mut().chunks_exact_mut(4).enumerate() {
+        let (x, y) = (index as u32 % size, index as u32 / size);
+        let alpha = if (x / 64 + y / 64) % 3 == 0 { 0 } else { 255 };
+        pixel.copy_from_slice(&[(x * 255 / size) as u8, (y * 255 / size) as u8, 128, alpha]);
+    }
+    pixmap
+}
+
+// The implementations from before the hot paths were sliced, as baseline
+fn pixmap_to_image_per_pixel(pixmap: Pixmap) -> RgbaImage {
+    let pixmap_clone = pixmap.clone();
+    let mut img = RgbaImage::new(pixmap_clone.width(), pixmap.height());
+    for y in 0..pixmap_clone.height() {
+        for x in 0..pixmap_clone.width() {
+            if let Some(pixel) = pixmap_clone.pixel(x, y) {
+                img.put_pixel(
+                    x,
+                    y,
+                    Rgba([pixel.red(), pixel.green(), pixel.blue(), pixel.alpha()]),
+                );
+            }
+        }
+    }
+    img
+}
+
+fn monochrome_per_pixel(
+    image: RgbaImage,
+    threshold: u8,
+    color: [u8; 3],
+    invert: bool,
+) -> RgbaImage {
+    let mut mono_img: RgbaImage = ImageBuffer::new(image.width(), image.height());
+    for (x, y, pixel) in image.enumerate_pixels() {
+        let rgba = pixel.0;
+        let luma = 0.299 * rgba[0] as f32 + 0.587 * rgba[1] as f32 + 0.114 * rgba[2] as f32;
+        let threshold_reached = luma >= threshold as f32 && rgba[3] > 0;
+        let mono_pixel = match (threshold_reached, invert) {
+            (false, false) | (true, true) => Rgba([0u8, 0u8, 0u8, 0u8]),
+            _ => Rgba([color[0], color[1], color[2], rgba[3]]),
+        };
+        mono_img.put_pixel(x, y, mono_pixel);
+    }
+    mono_img
+}
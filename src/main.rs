@@ -29,9 +29,10 @@ use std::boxed::Box;
 use std::error;
 
 use self::application::GtkTestApplication;
+use self::objects::data_file::DataFile;
 use self::window::GtkTestWindow;
 
-use config::{APP_ID, GETTEXT_PACKAGE, LOCALEDIR, PKGDATADIR};
+use config::{APP_ID, GETTEXT_PACKAGE, LOCALEDIR};
 use gettextrs::{bind_textdomain_codeset, bindtextdomain, textdomain};
 use gtk::prelude::*;
 use gtk::{gio, glib};
@@ -51,8 +52,8 @@ fn main() -> glib::ExitCode {
     textdomain(GETTEXT_PACKAGE).expect("Unable to switch to the text domain");
 
     // Load resources
-    let resources = gio::Resource::load(PKGDATADIR.to_owned() + "/folder_icon.gresource")
-        .expect("Could not load resources");
+    let resources_path = DataFile::find("folder_icon.gresource").expect("Could not find resources");
+    let resources = gio::Resource::load(resources_path).expect("Could not load resources");
     gio::resources_register(&resources);
 
     // Create a new GtkApplication. The application manages our main loop,
@@ -0,0 +1,92 @@
+use adw::prelude::{AppInfoExt, Cast, FileExt};
+use gtk::gio;
+use log::*;
+
+use crate::objects::iconic_error::IconicError;
+
+const SYMBOLIC_SUFFIX: &str = "-symbolic";
+
+/* The icon of an app, found through the Icon= key of its desktop file.
+Themed icons are looked up in the icon theme, which picks an SVG over a PNG if the theme has one */
+#[derive(Debug, Clone)]
+pub struct AppIcon {
+    pub app_name: String,
+    pub file: gio::File,
+    // Symbolic icons are a single color, so they are shown in monochrome
+    pub is_symbolic: bool,
+}
+
+impl AppIcon {
+    pub fn from_app_info(
+        app_info: &gio::AppInfo,
+        icon_theme: &gtk::IconTheme,
+        size: i32,
+    ) -> Result<Self, IconicError> {
+        let app_name = app_info.display_name().to_string();
+        let Some(icon) = app_info.icon() else {
+            return Err(IconicError::MissingAppIcon(app_name));
+        };
+        debug!("Icon of {}: {:?}", app_name, icon);
+
+        // Icon= can also be an absolute path instead of a name in the icon theme
+        if let Some(file_icon) = icon.downcast_ref::<gio::FileIcon>() {
+            let file = file_icon.file();
+            let is_symbolic = file
+                .basename()
+                .and_then(|name| {
+                    name.file_stem()
+                        .map(|stem| stem.to_string_lossy().to_string())
+                })
+                .is_some_and(|stem| stem.ends_with(SYMBOLIC_SUFFIX));
+            return Ok(Self {
+                app_name,
+                file,
+                is_symbolic,
+            });
+        }
+        let names: Vec<String> = match icon.downcast_ref::<gio::ThemedIcon>() {
+            Some(themed_icon) => themed_icon
+                .names()
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            None => vec![],
+        };
+        for name in names.iter().filter(|name| icon_theme.has_icon(name)) {
+            let symbolic_name = match name.ends_with(SYMBOLIC_SUFFIX) {
+                true => name.clone(),
+                false => format!("{}{}", name, SYMBOLIC_SUFFIX),
+            };
+            let (lookup_name, is_symbolic) = match icon_theme.has_icon(&symbolic_name) {
+                true => (symbolic_name, true),
+                false => (name.clone(), false),
+            };
+            let paintable = icon_theme.lookup_icon(
+                &lookup_name,
+                &[],
+                size,
+                1,
+                gtk::TextDirection::None,
+                gtk::IconLookupFlags::empty(),
+            );
+            if let Some(file) = paintable.file() {
+                return Ok(Self {
+                    app_name,
+                    file,
+                    is_symbolic,
+                });
+            }
+        }
+        Err(IconicError::MissingAppIcon(app_name))
+    }
+
+    // Apps that are shown in the app grid, sorted by name
+    pub fn installed_apps() -> Vec<gio::AppInfo> {
+        let mut apps: Vec<gio::AppInfo> = gio::AppInfo::all()
+            .into_iter()
+            .filter(|app| app.should_show() && app.icon().is_some())
+            .collect();
+        apps.sort_by_key(|app| app.display_name().to_lowercase());
+        apps
+    }
+}
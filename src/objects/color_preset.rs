@@ -0,0 +1,55 @@
+use gio::prelude::{SettingsExt, SettingsExtManual};
+use gtk::glib;
+
+use crate::objects::recolor::HexColor;
+
+// selected-accent-color is set to this followed by the id when a preset is selected
+const SELECTED_PREFIX: &str = "preset:";
+
+// A named pair of custom folder colors, stored in the custom-color-presets setting as (id, name, primary, secondary)
+#[derive(Debug, Clone, PartialEq, glib::Variant)]
+pub struct ColorPreset {
+    // Never changes, so renaming or moving a preset does not break icons that use it.
+    // Contains no '-', as it is stored in the file name of generated icons
+    pub id: String,
+    pub name: String,
+    pub primary: HexColor,
+    pub secondary: HexColor,
+}
+
+impl ColorPreset {
+    pub fn new(name: &str, primary: HexColor, secondary: HexColor) -> Self {
+        Self {
+            id: glib::uuid_string_random().replace('-', ""),
+            name: name.to_string(),
+            primary: primary.to_lowercase(),
+            secondary: secondary.to_lowercase(),
+        }
+    }
+
+    pub fn load_all(settings: &gio::Settings) -> Vec<Self> {
+        settings.get("custom-color-presets")
+    }
+
+    pub fn store_all(settings: &gio::Settings, presets: &[Self]) -> Result<(), glib::BoolError> {
+        settings.set("custom-color-presets", presets.to_vec())
+    }
+
+    pub fn find(settings: &gio::Settings, id: &str) -> Option<Self> {
+        Self::load_all(settings)
+            .into_iter()
+            .find(|preset| preset.id == id)
+    }
+
+    // The preset currently used for the folder, if any
+    pub fn selected(settings: &gio::Settings) -> Option<Self> {
+        let selected_color = settings.string("selected-accent-color");
+        let id = selected_color.strip_prefix(SELECTED_PREFIX)?;
+        Self::find(settings, id)
+    }
+
+    // The value of selected-accent-color for this preset
+    pub fn selected_value(&self) -> String {
+        format!("{}{}", SELECTED_PREFIX, self.id)
+    }
+}
@@ -0,0 +1,128 @@
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader, RgbaImage};
+use lcms2::{ColorSpaceSignature, Flags, Intent, PixelFormat, Profile, Transform};
+use log::*;
+use std::fs;
+use std::io::{Cursor, Write};
+use std::path::Path;
+
+use crate::objects::iconic_error::IconicError;
+
+/* Everything in Iconic is drawn in sRGB, like the folders themselves. Images with an embedded ICC profile,
+like wide-gamut logos exported by designers, are converted to sRGB when they are loaded. Otherwise their
+colors are read as sRGB and come out desaturated or oversaturated */
+
+// The ICC profile embedded in raster image data, only the header is decoded to find it
+pub fn embedded_profile(data: &[u8], format: ImageFormat) -> Option<Vec<u8>> {
+    let mut decoder = ImageReader::with_format(Cursor::new(data), format)
+        .into_decoder()
+        .ok()?;
+    decoder.icc_profile().ok().flatten()
+}
+
+/* Converts the image from its embedded profile to sRGB. Images without a profile are sRGB already,
+and images with a profile that can not be used are kept as they are, with a warning in the log */
+pub fn convert_to_srgb(image: DynamicImage, data: &[u8], format: ImageFormat) -> DynamicImage {
+    let Some(icc) = embedded_profile(data, format) else {
+        return image;
+    };
+    match transform_to_srgb(&image, &icc) {
+        Ok(converted) => {
+            debug!(
+                "Converted image with {} byte ICC profile to sRGB",
+                icc.len()
+            );
+            DynamicImage::ImageRgba8(converted)
+        }
+        Err(err) => {
+            warn!("Ignoring embedded ICC profile: {}", err);
+            image
+        }
+    }
+}
+
+fn transform_to_srgb(image: &DynamicImage, icc: &[u8]) -> Result<RgbaImage, String> {
+    let to_error = |err: lcms2::Error| err.to_string();
+    let profile = Profile::new_icc(icc).map_err(to_error)?;
+    let srgb = Profile::new_srgb();
+    let (width, height) = (image.width(), image.height());
+    let mut pixels: Vec<[u8; 4]> = vec![[0; 4]; width as usize * height as usize];
+    match profile.color_space() {
+        ColorSpaceSignature::RgbData => {
+            let source: Vec<[u8; 4]> = image.to_rgba8().pixels().map(|pixel| pixel.0).collect();
+            let transform = Transform::new_flags(
+                &profile,
+                PixelFormat::RGBA_8,
+                &srgb,
+                PixelFormat::RGBA_8,
+                Intent::Perceptual,
+                Flags::COPY_ALPHA,
+            )
+            .map_err(to_error)?;
+            transform.transform_pixels(&source, &mut pixels);
+        }
+        // Grayscale images can have a gray profile, these become RGB with the same gray in every channel
+        ColorSpaceSignature::GrayData => {
+            let source: Vec<[u8; 2]> = image
+                .to_luma_alpha8()
+                .pixels()
+                .map(|pixel| pixel.0)
+                .collect();
+            let transform = Transform::new_flags(
+                &profile,
+                PixelFormat::GRAYA_8,
+                &srgb,
+                PixelFormat::RGBA_8,
+                Intent::Perceptual,
+                Flags::COPY_ALPHA,
+            )
+            .map_err(to_error)?;
+            transform.transform_pixels(&source, &mut pixels);
+        }
+        // The image crate already turns CMYK into RGB, without the profile there is nothing left to match
+        color_space => return Err(format!("unsupported color space {:?}", color_space)),
+    }
+    Ok(RgbaImage::from_raw(width, height, pixels.into_flattened()).unwrap())
+}
+
+/* Encodes the generated icon as PNG. With embed_profile the sRGB profile is written in an iCCP chunk,
+so apps that do color management do not have to guess what the colors mean */
+pub fn encode_png(image: &DynamicImage, embed_profile: bool) -> Result<Vec<u8>, IconicError> {
+    let mut data = vec![];
+    if !embed_profile {
+        image
+            .write_to(&mut Cursor::new(&mut data), ImageFormat::Png)
+            .map_err(|err| IconicError::Io(err.to_string()))?;
+        return Ok(data);
+    }
+    let image = image.to_rgba8();
+    let to_error = |err: png::EncodingError| IconicError::Io(err.to_string());
+    let mut encoder = png::Encoder::new(&mut data, image.width(), image.height());
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(to_error)?;
+    // The iCCP chunk has to come before the image data
+    writer
+        .write_chunk(png::chunk::iCCP, &srgb_iccp_chunk()?)
+        .map_err(to_error)?;
+    writer.write_image_data(image.as_raw()).map_err(to_error)?;
+    writer.finish().map_err(to_error)?;
+    Ok(data)
+}
+
+pub fn save_png(image: &DynamicImage, path: &Path, embed_profile: bool) -> Result<(), IconicError> {
+    Ok(fs::write(path, encode_png(image, embed_profile)?)?)
+}
+
+// Profile name, a zero byte, compression method 0 (zlib) and the compressed profile
+fn srgb_iccp_chunk() -> Result<Vec<u8>, IconicError> {
+    let icc = Profile::new_srgb()
+        .icc()
+        .map_err(|err| IconicError::Io(err.to_string()))?;
+    let mut chunk = b"sRGB\0\0".to_vec();
+    let mut compressor = ZlibEncoder::new(&mut chunk, Compression::default());
+    compressor.write_all(&icc)?;
+    compressor.finish()?;
+    Ok(chunk)
+}
@@ -0,0 +1,195 @@
+use image::codecs::gif::GifDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::*;
+use log::*;
+use std::io::Cursor;
+
+use crate::objects::file::SourceFormat;
+use crate::objects::iconic_error::IconicError;
+
+// Animations are searched this far for a frame that is not empty
+const MAX_FRAMES: usize = 50;
+const ICO_HEADER_SIZE: usize = 6;
+const ICO_ENTRY_SIZE: usize = 16;
+const ICNS_HEADER_SIZE: usize = 8;
+const EPS_HEADER_SIZE: usize = 30;
+
+/* Files that hold the same icon in several sizes, like ICO and macOS ICNS files.
+Every image in it is decoded, so the best one can be picked */
+pub struct ImageContainer {
+    pub images: Vec<DynamicImage>,
+    format: SourceFormat,
+}
+
+impl ImageContainer {
+    pub fn from_ico(data: &[u8]) -> Result<Self, IconicError> {
+        let format = SourceFormat::Raster(ImageFormat::Ico);
+        let count = Self::read_u16_le(data, 4).ok_or_else(|| Self::damaged(format))?;
+        let mut images = Vec::new();
+        for index in 0..count as usize {
+            let entry_start = ICO_HEADER_SIZE + index * ICO_ENTRY_SIZE;
+            let Some(entry) = data.get(entry_start..entry_start + ICO_ENTRY_SIZE) else {
+                break;
+            };
+            let size = Self::read_u32_le(entry, 8).unwrap_or_default() as usize;
+            let offset = Self::read_u32_le(entry, 12).unwrap_or_default() as usize;
+            let Some(image_data) = data.get(offset..offset.saturating_add(size)) else {
+                warn!("ICO entry {} is outside of the file", index);
+                continue;
+            };
+            match Self::decode_ico_entry(entry, image_data) {
+                Ok(image) => images.push(image),
+                Err(e) => warn!("Could not decode ICO entry {}: {}", index, e),
+            }
+        }
+        Ok(Self { images, format })
+    }
+
+    // Newer ICO files store PNG images, older ones bitmaps without a file header
+    fn decode_ico_entry(entry: &[u8], image_data: &[u8]) -> ImageResult<DynamicImage> {
+        if let Ok(ImageFormat::Png) = image::guess_format(image_data) {
+            return image::load_from_memory_with_format(image_data, ImageFormat::Png);
+        }
+        // The image crate only decodes these bitmaps as part of an ICO, so put it in one of its own
+        let mut single_entry =
+            Vec::with_capacity(ICO_HEADER_SIZE + ICO_ENTRY_SIZE + image_data.len());
+        single_entry.extend_from_slice(&[0, 0, 1, 0, 1, 0]);
+        single_entry.extend_from_slice(&entry[..12]);
+        let image_offset = (ICO_HEADER_SIZE + ICO_ENTRY_SIZE) as u32;
+        single_entry.extend_from_slice(&image_offset.to_le_bytes());
+        single_entry.extend_from_slice(image_data);
+        image::load_from_memory_with_format(&single_entry, ImageFormat::Ico)
+    }
+
+    /* ICNS files are a list of (type, length, data) entries. The modern entries (ic07 to ic14 and friends)
+    contain PNG images, the old run-length encoded ones and JPEG 2000 images are skipped */
+    pub fn from_icns(data: &[u8]) -> Result<Self, IconicError> {
+        let format = SourceFormat::Icns;
+        let file_length = Self::read_u32_be(data, 4).ok_or_else(|| Self::damaged(format))?;
+        let file_length = (file_length as usize).min(data.len());
+        let mut images = Vec::new();
+        let mut position = ICNS_HEADER_SIZE;
+        while position + ICNS_HEADER_SIZE <= file_length {
+            let entry_type = String::from_utf8_lossy(&data[position..position + 4]).to_string();
+            let entry_length = Self::read_u32_be(data, position + 4).unwrap_or_default() as usize;
+            if entry_length < ICNS_HEADER_SIZE {
+                warn!("ICNS entry {} has an invalid length", entry_type);
+                break;
+            }
+            let entry_end = (position + entry_length).min(file_length);
+            let entry_data = &data[position + ICNS_HEADER_SIZE..entry_end];
+            match image::guess_format(entry_data) {
+                Ok(ImageFormat::Png) => {
+                    match image::load_from_memory_with_format(entry_data, ImageFormat::Png) {
+                        Ok(image) => images.push(image),
+                        Err(e) => warn!("Could not decode ICNS entry {}: {}", entry_type, e),
+                    }
+                }
+                _ => debug!("Skipping ICNS entry {}", entry_type),
+            }
+            position += entry_length;
+        }
+        Ok(Self { images, format })
+    }
+
+    pub fn largest(self) -> Result<DynamicImage, IconicError> {
+        debug!(
+            "Images found in {}: {:?}",
+            self.format.name(),
+            self.images
+                .iter()
+                .map(|image| image.dimensions())
+                .collect::<Vec<(u32, u32)>>()
+        );
+        let format = self.format;
+        self.images
+            .into_iter()
+            .max_by_key(|image| image.width() as u64 * image.height() as u64)
+            .ok_or_else(|| IconicError::DecodeFailed {
+                format: format.name(),
+                reason: String::from("Contains no images that can be decoded"),
+            })
+    }
+
+    /* Binary EPS files have a header with the place of the PostScript, a WMF and a TIFF preview.
+    PostScript can not be rendered without Ghostscript, so the TIFF preview is used */
+    pub fn eps_preview(data: &[u8]) -> Result<DynamicImage, IconicError> {
+        let format = SourceFormat::Eps;
+        if data.len() < EPS_HEADER_SIZE || !data.starts_with(&[0xc5, 0xd0, 0xd3, 0xc6]) {
+            return Err(IconicError::UnsupportedFormat(String::from(
+                "EPS without preview image",
+            )));
+        }
+        let offset = Self::read_u32_le(data, 20).ok_or_else(|| Self::damaged(format))? as usize;
+        let length = Self::read_u32_le(data, 24).ok_or_else(|| Self::damaged(format))? as usize;
+        if offset == 0 || length == 0 {
+            return Err(IconicError::UnsupportedFormat(String::from(
+                "EPS without preview image",
+            )));
+        }
+        let preview = data
+            .get(offset..offset.saturating_add(length))
+            .ok_or_else(|| Self::damaged(format))?;
+        image::load_from_memory_with_format(preview, ImageFormat::Tiff)
+            .map_err(|err| IconicError::from_image_error(err, &format.name()))
+    }
+
+    /* Animations often start with an empty frame and fade in, so the first frame with something on it is used.
+    Images that are not animated are decoded like any other image */
+    pub fn animation_frame(
+        data: &[u8],
+        image_format: ImageFormat,
+    ) -> Result<DynamicImage, IconicError> {
+        let format = SourceFormat::Raster(image_format);
+        let to_error = |err: ImageError| IconicError::from_image_error(err, &format.name());
+        let frames = match image_format {
+            ImageFormat::Gif => GifDecoder::new(Cursor::new(data))
+                .map_err(to_error)?
+                .into_frames(),
+            ImageFormat::WebP => {
+                let decoder = WebPDecoder::new(Cursor::new(data)).map_err(to_error)?;
+                if !decoder.has_animation() {
+                    return DynamicImage::from_decoder(decoder).map_err(to_error);
+                }
+                decoder.into_frames()
+            }
+            _ => return image::load_from_memory_with_format(data, image_format).map_err(to_error),
+        };
+        let mut first_frame = None;
+        for frame in frames.take(MAX_FRAMES) {
+            let frame = frame.map_err(to_error)?.into_buffer();
+            if frame.pixels().any(|pixel| pixel[3] > 0) {
+                return Ok(DynamicImage::ImageRgba8(frame));
+            }
+            first_frame.get_or_insert(frame);
+        }
+        first_frame
+            .map(DynamicImage::ImageRgba8)
+            .ok_or_else(|| Self::damaged(format))
+    }
+
+    fn damaged(format: SourceFormat) -> IconicError {
+        IconicError::DecodeFailed {
+            format: format.name(),
+            reason: String::from("The file is too short"),
+        }
+    }
+
+    fn read_u16_le(data: &[u8], offset: usize) -> Option<u16> {
+        Some(u16::from_le_bytes(
+            data.get(offset..offset + 2)?.try_into().ok()?,
+        ))
+    }
+
+    fn read_u32_le(data: &[u8], offset: usize) -> Option<u32> {
+        Some(u32::from_le_bytes(
+            data.get(offset..offset + 4)?.try_into().ok()?,
+        ))
+    }
+
+    fn read_u32_be(data: &[u8], offset: usize) -> Option<u32> {
+        Some(u32::from_be_bytes(
+            data.get(offset..offset + 4)?.try_into().ok()?,
+        ))
+    }
+}
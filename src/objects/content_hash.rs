@@ -0,0 +1,81 @@
+use image::{imageops::FilterType, DynamicImage, GenericImageView};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+/* SHA-256 of the bytes a top image was loaded from. It names the top image in the cache and ends up in
+the file names of generated icons, so it has to stay the same between versions of Rust and Iconic.
+Hashing the source instead of the decoded pixels also keeps SVGs the same at every render size */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ContentHash([u8; 32]);
+
+impl ContentHash {
+    pub fn of(data: &[u8]) -> Self {
+        Self(Sha256::digest(data).into())
+    }
+
+    // The first 8 bytes, enough for keys that only live in memory like those of the render cache
+    pub fn as_u64(&self) -> u64 {
+        u64::from_le_bytes(self.0[..8].try_into().unwrap())
+    }
+}
+
+// Lowercase hex, as used in file names
+impl fmt::Display for ContentHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+// Hashes that differ in at most this many bits are the same image, resized or exported again
+const NEAR_DUPLICATE_DISTANCE: u32 = 6;
+
+/* Difference hash of what an image looks like. The image is shrunk to 9x8 gray pixels and every bit
+tells whether a pixel is darker than its right neighbour, so the same logo at another size or in another
+format gets (almost) the same hash. Transparent parts count as white, most logos are drawn on nothing */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PerceptualHash(pub u64);
+
+impl PerceptualHash {
+    // None for images that are too small to say anything about, like the empty placeholder
+    pub fn of(image: &DynamicImage) -> Option<Self> {
+        let (width, height) = image.dimensions();
+        if width < 2 || height < 2 {
+            return None;
+        }
+        let small = image.resize_exact(9, 8, FilterType::Triangle).into_rgba8();
+        let gray: Vec<f32> = small
+            .pixels()
+            .map(|pixel| {
+                let luma =
+                    0.299 * pixel[0] as f32 + 0.587 * pixel[1] as f32 + 0.114 * pixel[2] as f32;
+                let alpha = pixel[3] as f32 / 255.0;
+                luma * alpha + 255.0 * (1.0 - alpha)
+            })
+            .collect();
+        let mut hash = 0u64;
+        for y in 0..8 {
+            for x in 0..8 {
+                let left = gray[y * 9 + x];
+                let right = gray[y * 9 + x + 1];
+                hash = (hash << 1) | (left < right) as u64;
+            }
+        }
+        Some(Self(hash))
+    }
+
+    pub fn distance(&self, other: &Self) -> u32 {
+        (self.0 ^ other.0).count_ones()
+    }
+
+    pub fn is_near_duplicate(&self, other: &Self) -> bool {
+        self.distance(other) <= NEAR_DUPLICATE_DISTANCE
+    }
+
+    pub fn to_hex(self) -> String {
+        format!("{:016x}", self.0)
+    }
+
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        u64::from_str_radix(hex, 16).ok().map(Self)
+    }
+}
@@ -0,0 +1,75 @@
+use gtk::glib;
+use log::*;
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::config::PKGDATADIR;
+
+/* Finds the files that are installed together with iconic, like the gresource bundle.
+PKGDATADIR is tried first, after that the XDG data directories. So native, distro and Flatpak builds
+all find their files, wherever they are installed */
+pub struct DataFile;
+
+#[derive(Debug)]
+pub struct DataFileError {
+    pub name: String,
+    pub searched: Vec<PathBuf>,
+}
+
+impl fmt::Display for DataFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let searched: Vec<String> = self
+            .searched
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        write!(
+            f,
+            "Could not find \"{}\", searched in: {}",
+            self.name,
+            searched.join(", ")
+        )
+    }
+}
+
+impl Error for DataFileError {}
+
+impl DataFile {
+    // Name is relative to the data directory of iconic, like "folder_icon.gresource"
+    pub fn find(name: &str) -> Result<PathBuf, DataFileError> {
+        let searched: Vec<PathBuf> = Self::search_directories()
+            .into_iter()
+            .map(|directory| directory.join(name))
+            .collect();
+        match searched.iter().find(|path| path.exists()) {
+            Some(path) => {
+                debug!("Found data file {} at {:?}", name, path);
+                Ok(path.clone())
+            }
+            None => Err(DataFileError {
+                name: name.to_string(),
+                searched,
+            }),
+        }
+    }
+
+    fn search_directories() -> Vec<PathBuf> {
+        let mut directories = vec![PathBuf::from(PKGDATADIR)];
+        // "folder_icon", the same directory PKGDATADIR uses inside the prefix
+        let package_directory = Path::new(PKGDATADIR)
+            .file_name()
+            .unwrap_or("folder_icon".as_ref());
+        directories.extend(
+            std::iter::once(glib::user_data_dir())
+                .chain(glib::system_data_dirs())
+                .map(|data_dir| data_dir.join(package_directory)),
+        );
+        // Makes it possible to run development builds without installing the data files
+        if cfg!(debug_assertions) {
+            directories.push(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("data"));
+        }
+        directories.dedup();
+        directories
+    }
+}
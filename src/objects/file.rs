@@ -1,14 +1,120 @@
-use adw::prelude::FileExt;
+use adw::prelude::{Cast, FileExt, InputStreamExtManual};
 use gio::{Cancellable, FileQueryInfoFlags};
-use gtk::gio;
+use gtk::{gio, glib};
 use image::*;
 use log::*;
 use resvg::tiny_skia::Pixmap;
 use resvg::usvg::{Options, Tree};
-use std::error::Error;
+use std::fmt;
 use std::fs;
-use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::Read;
 use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::objects::color_profile;
+use crate::objects::container::ImageContainer;
+use crate::objects::content_hash::{ContentHash, PerceptualHash};
+use crate::objects::iconic_error::IconicError;
+use crate::objects::pixels;
+use crate::objects::resampling::Resampling;
+
+// Documents that can be used as top image next to the image/* MIME types
+pub const DOCUMENT_MIME_TYPES: [&str; 3] = ["application/pdf", "image/x-eps", "application/eps"];
+
+// The format of image data, found by looking at the data itself instead of the file name or MIME type
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SourceFormat {
+    Svg,
+    // Gzip compressed SVG, usvg decompresses these by itself
+    Svgz,
+    // macOS icons, the image crate can not read these
+    Icns,
+    // The first page is rendered with poppler
+    Pdf,
+    // Only EPS files with a TIFF preview can be shown, PostScript itself is not interpreted
+    Eps,
+    Raster(ImageFormat),
+}
+
+impl SourceFormat {
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        if data.starts_with(&[0x1f, 0x8b]) {
+            return Some(Self::Svgz);
+        }
+        if data.starts_with(b"icns") {
+            return Some(Self::Icns);
+        }
+        if data.starts_with(b"%PDF-") {
+            return Some(Self::Pdf);
+        }
+        // Binary EPS files made on Windows start with their own header, plain ones are PostScript text
+        if data.starts_with(&[0xc5, 0xd0, 0xd3, 0xc6])
+            || (data.starts_with(b"%!PS-Adobe")
+                && data
+                    .split(|byte| *byte == b'\n')
+                    .next()
+                    .is_some_and(|line| line.windows(4).any(|word| word == b"EPSF")))
+        {
+            return Some(Self::Eps);
+        }
+        if let Ok(format) = image::guess_format(data) {
+            return Some(Self::Raster(format));
+        }
+        // SVG has no magic bytes, so look for the svg element at the start of the document
+        let start = String::from_utf8_lossy(&data[..data.len().min(4096)]);
+        match start
+            .trim_start_matches('\u{feff}')
+            .trim_start()
+            .starts_with('<')
+            && start.contains("<svg")
+        {
+            true => Some(Self::Svg),
+            false => None,
+        }
+    }
+
+    pub fn is_svg(&self) -> bool {
+        matches!(self, Self::Svg | Self::Svgz)
+    }
+
+    // Formats that are rendered at the requested size, instead of being resized afterwards
+    pub fn is_vector(&self) -> bool {
+        self.is_svg() || *self == Self::Pdf
+    }
+
+    // Used in error messages, like "PNG" or "SVG"
+    pub fn name(&self) -> String {
+        match self {
+            Self::Svg => String::from("SVG"),
+            Self::Svgz => String::from("SVGZ"),
+            Self::Icns => String::from("ICNS"),
+            Self::Pdf => String::from("PDF"),
+            Self::Eps => String::from("EPS"),
+            Self::Raster(format) => match format.extensions_str().first() {
+                Some(extension) => extension.to_uppercase(),
+                None => format!("{:?}", format),
+            },
+        }
+    }
+}
+
+/* The parsed SVG of a top image, so it can be rendered at the size it ends up with in the icon.
+Shared, because the tree can be large and the file is cloned for every preview */
+#[derive(Clone)]
+pub struct SvgTree(pub Arc<Tree>);
+
+impl fmt::Debug for SvgTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let size = self.0.size();
+        write!(f, "SvgTree({}x{})", size.width(), size.height())
+    }
+}
+
+impl PartialEq for SvgTree {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct File {
@@ -18,110 +124,219 @@ pub struct File {
     pub extension: String,
     pub dynamic_image: DynamicImage,
     pub thumbnail: DynamicImage,
-    pub hash: u64,
+    // Only set for SVG images, everything else is a bitmap from the start
+    pub svg_tree: Option<SvgTree>,
+    // Names the top image in the cache, see ContentHash
+    pub hash: ContentHash,
+    pub perceptual_hash: Option<PerceptualHash>,
 }
 
 impl File {
     pub fn path_str(&self) -> String {
-        self.path.clone().into_os_string().into_string().unwrap()
+        self.path.to_string_lossy().to_string()
     }
-    pub fn new(file: gio::File, size: i32, thumbnail_size: i32) -> Result<Self, Box<dyn Error>> {
-        let temp_path = file.path().unwrap();
+    pub fn new(
+        file: gio::File,
+        size: i32,
+        thumbnail_size: i32,
+        resampling: Resampling,
+    ) -> Result<Self, IconicError> {
         let file_info =
             file.query_info("standard::", FileQueryInfoFlags::NONE, Cancellable::NONE)?;
-        let file_name = file_info.name().into_os_string().into_string().unwrap();
-        let period_split: Vec<&str> = file_name.split(".").collect();
-        let file_extension = format!(".{}", period_split.last().unwrap());
-        let mime_type = file_info.content_type();
-        debug!("Mime type: {:?}", mime_type);
-        let dynamic_image = if mime_type == Some("image/svg+xml".into()) {
-            let path = &temp_path.as_os_str().to_str().unwrap();
-            Self::load_svg(path, size)?
-        } else {
-            match image::open(temp_path.clone().into_os_string()) {
-                Err(_) => {
-                    let mut image = ImageReader::open(temp_path.clone().into_os_string())?;
-                    image.set_format(ImageFormat::Png);
-                    image.decode()?
-                }
-                Ok(x) => x,
-            }
-        };
-        let name_no_extension = file_name.replace(&file_extension, "");
-        let hash = Self::create_hash(&dynamic_image);
-        debug!("hash of created file: {}", hash);
-        let mut thumbnail = DynamicImage::new_rgb8(0, 0);
-        if thumbnail_size > 0 {
-            thumbnail = if file_extension == ".svg" {
-                let path = &temp_path.as_os_str().to_str().unwrap();
-                Self::load_svg(path, thumbnail_size)?
-            } else {
-                dynamic_image.clone().resize(
-                    thumbnail_size as u32,
-                    thumbnail_size as u32,
-                    imageops::FilterType::Nearest,
-                )
-            };
-        }
-        Ok(Self {
-            files: Some(file),
-            path: temp_path.into(),
-            extension: mime_type.unwrap().into(),
-            filename: name_no_extension,
-            dynamic_image,
-            thumbnail,
-            hash,
-        })
+        // Only used to name and guess the type of the image, so names that are not UTF-8 are fine
+        let file_name = file_info.name().to_string_lossy().to_string();
+        // Read through GIO, so files without a local path like sftp:// or portal URIs work too
+        let stream = file.read(Cancellable::NONE)?;
+        let mut iconic_file = Self::from_stream(
+            stream.upcast_ref(),
+            file_info.content_type().as_deref(),
+            &file_name,
+            size,
+            thumbnail_size,
+            resampling,
+        )?;
+        iconic_file.path = file.path().unwrap_or_default();
+        iconic_file.files = Some(file);
+        Ok(iconic_file)
+    }
+
+    pub fn from_stream(
+        stream: &gio::InputStream,
+        mime_type: Option<&str>,
+        file_name: &str,
+        size: i32,
+        thumbnail_size: i32,
+        resampling: Resampling,
+    ) -> Result<Self, IconicError> {
+        let mut data = Vec::new();
+        stream.clone().into_read().read_to_end(&mut data)?;
+        Self::from_data(
+            &data,
+            mime_type,
+            file_name,
+            size,
+            thumbnail_size,
+            resampling,
+        )
+    }
+
+    pub fn from_bytes(
+        bytes: &glib::Bytes,
+        mime_type: Option<&str>,
+        file_name: &str,
+        size: i32,
+        thumbnail_size: i32,
+        resampling: Resampling,
+    ) -> Result<Self, IconicError> {
+        Self::from_data(
+            bytes,
+            mime_type,
+            file_name,
+            size,
+            thumbnail_size,
+            resampling,
+        )
     }
 
     pub fn from_path_string(
         path: &str,
         size: i32,
         thumbnail_size: i32,
-    ) -> Result<Self, Box<dyn Error>> {
+        resampling: Resampling,
+    ) -> Result<Self, IconicError> {
         //let thumbnail = file.clone().resize(255, 255, imageops::FilterType::Nearest);
         let file = gio::File::for_path(PathBuf::from(path).as_path());
-        Self::new(file, size, thumbnail_size)
+        Self::new(file, size, thumbnail_size, resampling)
     }
 
     pub fn from_path(
         path: PathBuf,
         size: i32,
         thumbnail_size: i32,
-    ) -> Result<Self, Box<dyn Error>> {
+        resampling: Resampling,
+    ) -> Result<Self, IconicError> {
         //let thumbnail = file.clone().resize(255, 255, imageops::FilterType::Nearest);
         let file = gio::File::for_path(path);
-        Self::new(file, size, thumbnail_size)
+        Self::new(file, size, thumbnail_size, resampling)
     }
 
-    pub fn from_image(image: DynamicImage, thumbnail_size: i32, filename: &str) -> Self {
-        let thumbnail = image.clone().resize(
-            thumbnail_size as u32,
-            thumbnail_size as u32,
-            imageops::FilterType::Nearest,
-        );
-        let hash = Self::create_hash(&image);
+    pub fn from_image(
+        image: DynamicImage,
+        thumbnail_size: i32,
+        filename: &str,
+        resampling: Resampling,
+    ) -> Self {
+        let thumbnail = resampling.resize(&image, thumbnail_size as u32, thumbnail_size as u32);
+        // There is no source data, so the pixels are hashed instead
+        let hash = ContentHash::of(image.as_bytes());
+        let perceptual_hash = PerceptualHash::of(&image);
         Self {
             files: None,
             path: "".into(),
             extension: ".dynamic".to_string(),
             filename: filename.to_string(),
             hash,
+            perceptual_hash,
             dynamic_image: image,
             thumbnail,
+            svg_tree: None,
         }
     }
 
-    pub fn load_svg(path: &str, size: i32) -> Result<DynamicImage, Box<dyn Error>> {
-        // Load the SVG file content
-        let svg_data = match fs::read(path) {
-            Ok(x) => x,
-            Err(_) => fs::read("/usr/share/icons/Adwaita/scalable/places/folder.svg")?,
+    /* For images that are not on disk, like the folders in the gresource bundle or the clipboard.
+    Without a MIME type it is guessed from the file name and the data */
+    pub fn from_data(
+        data: &[u8],
+        mime_type: Option<&str>,
+        file_name: &str,
+        size: i32,
+        thumbnail_size: i32,
+        resampling: Resampling,
+    ) -> Result<Self, IconicError> {
+        let mime_type = match mime_type {
+            Some(mime_type) => mime_type.to_string(),
+            None => gio::content_type_guess(Some(file_name), data).0.into(),
+        };
+        // The MIME type is only a hint, what the data really is decides how it is decoded
+        let format =
+            SourceFormat::detect(data).ok_or(IconicError::UnsupportedFormat(mime_type.clone()))?;
+        debug!("Mime type: {:?}, detected format: {:?}", mime_type, format);
+        let svg_tree = match format.is_svg() {
+            true => Some(SvgTree(Arc::new(Tree::from_data(
+                data,
+                &Options::default(),
+            )?))),
+            false => None,
+        };
+        let dynamic_image = match format {
+            // Containers with the same icon in several sizes, the largest makes the sharpest top image
+            SourceFormat::Raster(ImageFormat::Ico) => ImageContainer::from_ico(data)?.largest()?,
+            SourceFormat::Icns => ImageContainer::from_icns(data)?.largest()?,
+            SourceFormat::Eps => ImageContainer::eps_preview(data)?,
+            SourceFormat::Raster(image_format @ (ImageFormat::Gif | ImageFormat::WebP)) => {
+                ImageContainer::animation_frame(data, image_format)?
+            }
+            SourceFormat::Raster(image_format) => {
+                image::load_from_memory_with_format(data, image_format)
+                    .map_err(|err| IconicError::from_image_error(err, &format.name()))?
+            }
+            SourceFormat::Svg | SourceFormat::Svgz | SourceFormat::Pdf => {
+                Self::render_vector(data, svg_tree.as_ref(), size)?
+            }
+        };
+        let dynamic_image = match format {
+            SourceFormat::Raster(image_format) => {
+                color_profile::convert_to_srgb(dynamic_image, data, image_format)
+            }
+            _ => dynamic_image,
+        };
+        let hash = ContentHash::of(data);
+        let mut thumbnail = DynamicImage::new_rgb8(0, 0);
+        if thumbnail_size > 0 {
+            thumbnail = if format.is_vector() {
+                Self::render_vector(data, svg_tree.as_ref(), thumbnail_size)?
+            } else {
+                resampling.resize(&dynamic_image, thumbnail_size as u32, thumbnail_size as u32)
+            };
+        }
+        // The thumbnail is small already, which makes it quicker to shrink for the perceptual hash
+        let perceptual_hash = match thumbnail_size > 0 {
+            true => PerceptualHash::of(&thumbnail),
+            false => PerceptualHash::of(&dynamic_image),
+        };
+        let name_no_extension = match file_name.rsplit_once('.') {
+            Some((name, _)) => name,
+            None => file_name,
         };
+        Ok(Self {
+            files: None,
+            path: "".into(),
+            extension: mime_type,
+            filename: name_no_extension.to_string(),
+            dynamic_image,
+            thumbnail,
+            svg_tree,
+            hash,
+            perceptual_hash,
+        })
+    }
+
+    pub fn load_svg(path: &str, size: i32) -> Result<DynamicImage, IconicError> {
+        // Load the SVG file content
+        let svg_data = fs::read(path)
+            .map_err(|err| IconicError::Io(format!("Could not read \"{}\": {}", path, err)))?;
+        Self::load_svg_data(&svg_data, size)
+    }
+
+    pub fn load_svg_data(svg_data: &[u8], size: i32) -> Result<DynamicImage, IconicError> {
         // Create an SVG tree
         let opt = Options::default();
-        let rtree = Tree::from_data(&svg_data, &opt)?;
+        let rtree = Tree::from_data(svg_data, &opt)?;
+        Self::render_svg_tree(&rtree, size)
+    }
 
+    // Renders the SVG to fit in a square of the given size
+    pub fn render_svg_tree(rtree: &Tree, size: i32) -> Result<DynamicImage, IconicError> {
         // Specify the output dimensions (you can adjust these as needed)
         let width = rtree.size().width();
         let height = rtree.size().height();
@@ -132,45 +347,74 @@ impl File {
         let scale = scale_x.min(scale_y); // Maintain aspect ratio
 
         // Create a Pixmap to render into
-        let mut pixmap = Pixmap::new(size as u32, size as u32).unwrap();
+        let mut pixmap =
+            Pixmap::new(size as u32, size as u32).ok_or_else(|| IconicError::DecodeFailed {
+                format: String::from("SVG"),
+                reason: format!("Can not render at size {}", size),
+            })?;
 
         // Render the SVG tree to the Pixmap
         let _ = resvg::render(
-            &rtree,
+            rtree,
             usvg::Transform::from_scale(scale, scale),
             &mut pixmap.as_mut(),
         );
         Ok(Self::pixmap_to_image(pixmap))
     }
 
-    // Adds hash of file to file name
-    fn create_hash(image: &DynamicImage) -> u64 {
-        let mut hasher = DefaultHasher::new();
-        let _ = image.as_bytes().hash(&mut hasher);
-        hasher.finish()
-    }
-
-    fn pixmap_to_image(pixmap: Pixmap) -> DynamicImage {
-        // Create an empty RgbaImage with the same dimensions as the Pixmap.
-        let pixmap_clone = pixmap.clone();
-        let mut img = RgbaImage::new(pixmap_clone.width(), pixmap.height());
-
-        // Iterate over each pixel in the Pixmap.
-        for y in 0..pixmap_clone.height() {
-            for x in 0..pixmap_clone.width() {
-                // Get the pixel at (x, y). `pixel` returns an Option, we unwrap it safely because we know (x, y) is valid.
-                if let Some(pixel) = pixmap_clone.pixel(x, y) {
-                    // Copy the pixel's RGBA data into the RgbaImage.
-                    img.put_pixel(
-                        x,
-                        y,
-                        Rgba([pixel.red(), pixel.green(), pixel.blue(), pixel.alpha()]),
-                    );
-                }
+    // SVGs are parsed once in from_data, everything else that is rendered here is a PDF
+    fn render_vector(
+        data: &[u8],
+        svg_tree: Option<&SvgTree>,
+        size: i32,
+    ) -> Result<DynamicImage, IconicError> {
+        match svg_tree {
+            Some(svg_tree) => Self::render_svg_tree(&svg_tree.0, size),
+            None => Self::load_pdf_data(data, size),
+        }
+    }
+
+    /* Renders the first page of a PDF, scaled to fit in a square of the given size.
+    Only what is drawn on the page ends up in the image, so logos keep their transparent background */
+    pub fn load_pdf_data(pdf_data: &[u8], size: i32) -> Result<DynamicImage, IconicError> {
+        let to_error = |reason: String| IconicError::DecodeFailed {
+            format: String::from("PDF"),
+            reason,
+        };
+        let document = poppler::Document::from_bytes(&glib::Bytes::from(pdf_data), None)
+            .map_err(|err| to_error(err.to_string()))?;
+        let page = document
+            .page(0)
+            .ok_or_else(|| to_error(String::from("The document has no pages")))?;
+        let (width, height) = page.size();
+        let scale = size as f64 / width.max(height);
+
+        let mut surface = cairo::ImageSurface::create(cairo::Format::ARgb32, size, size)
+            .map_err(|err| to_error(format!("Can not render at size {}: {}", size, err)))?;
+        {
+            let context = cairo::Context::new(&surface).map_err(|err| to_error(err.to_string()))?;
+            context.scale(scale, scale);
+            page.render(&context);
+        }
+        surface.flush();
+
+        // Cairo stores premultiplied BGRA (on little endian machines) with padding at the end of every row
+        let stride = surface.stride() as usize;
+        let data = surface.data().map_err(|err| to_error(err.to_string()))?;
+        let mut img = RgbaImage::new(size as u32, size as u32);
+        for (y, row) in data.chunks(stride).take(size as usize).enumerate() {
+            for (x, pixel) in row.chunks_exact(4).take(size as usize).enumerate() {
+                let [blue, green, red, alpha] =
+                    u32::from_ne_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]).to_le_bytes();
+                let mut rgba = [red, green, blue, alpha];
+                pixels::demultiply(&mut rgba);
+                img.put_pixel(x as u32, y as u32, Rgba(rgba));
             }
         }
+        Ok(DynamicImage::ImageRgba8(img))
+    }
 
-        // Convert the RgbaImage to a DynamicImage.
-        DynamicImage::ImageRgba8(img)
+    pub fn pixmap_to_image(pixmap: Pixmap) -> DynamicImage {
+        DynamicImage::ImageRgba8(pixels::pixmap_to_rgba(pixmap))
     }
 }
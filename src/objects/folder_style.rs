@@ -0,0 +1,338 @@
+use gio::ResourceLookupFlags;
+use gtk::{gio, glib};
+use log::*;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::objects::file::File;
+use crate::objects::iconic_error::IconicError;
+use crate::objects::recolor::{HexColor, RecolorableSvg};
+use crate::objects::resampling::Resampling;
+
+pub const BUILT_IN_STYLE: &str = "adwaita";
+// The bundled folders are compiled into the gresource bundle
+const BUILT_IN_RESOURCE_DIRECTORY: &str = "/nl/emphisia/icon/folders";
+const MANIFEST_NAME: &str = "manifest.ini";
+const STYLE_GROUP: &str = "Folder Style";
+const COLORS_GROUP: &str = "Colors";
+
+/* Part of the folder where the top image fits, as fractions of the width and height of the folder.
+This is used to place and size the top image when it is loaded or reset */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SafeArea {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl SafeArea {
+    // The spot where the top image has always been placed on the Adwaita folder
+    pub const ADWAITA: SafeArea = SafeArea {
+        x: 0.35295,
+        y: 0.44742,
+        width: 0.2941,
+        height: 0.2941,
+    };
+
+    // The values of the x, y and size sliders that put the top image in the middle of the safe area
+    pub fn slider_values(&self) -> (f64, f64, f64) {
+        let x = (self.x + self.width / 2.0) * 100.0 - 50.0;
+        let y = (self.y + self.height / 2.0) * 100.0 - 50.0;
+        let scale_factor = 1.0 / self.width.min(self.height).max(0.01);
+        let size = (scale_factor * 10.0 - 10.0).clamp(0.0, 50.0);
+        let round = |value: f32, decimals: i32| {
+            let factor = 10f64.powi(decimals);
+            (value as f64 * factor).round() / factor
+        };
+        (round(x, 3), round(y, 3), round(size, 1))
+    }
+}
+
+// Where a folder SVG is stored, the bundled folders are resources and installed styles are files
+#[derive(Debug, Clone, PartialEq)]
+pub enum FolderSource {
+    Resource(String),
+    Path(PathBuf),
+}
+
+impl FolderSource {
+    fn built_in(file_name: &str) -> Self {
+        Self::Resource(format!("{}/{}", BUILT_IN_RESOURCE_DIRECTORY, file_name))
+    }
+
+    pub fn exists(&self) -> bool {
+        match self {
+            Self::Resource(path) => {
+                gio::resources_get_info(path, ResourceLookupFlags::NONE).is_ok()
+            }
+            Self::Path(path) => path.exists(),
+        }
+    }
+
+    pub fn load(&self) -> Result<Vec<u8>, IconicError> {
+        match self {
+            Self::Resource(path) => gio::resources_lookup_data(path, ResourceLookupFlags::NONE)
+                .map(|bytes| bytes.to_vec())
+                .map_err(|err| {
+                    IconicError::Io(format!("Could not load resource \"{}\": {}", path, err))
+                }),
+            Self::Path(path) => fs::read(path).map_err(|err| {
+                IconicError::Io(format!("Could not read \"{}\": {}", path.display(), err))
+            }),
+        }
+    }
+
+    pub fn to_file(
+        &self,
+        size: i32,
+        thumbnail_size: i32,
+        resampling: Resampling,
+    ) -> Result<File, IconicError> {
+        match self {
+            Self::Resource(path) => {
+                let file_name = path.rsplit('/').next().unwrap_or_default();
+                File::from_data(
+                    &self.load()?,
+                    None,
+                    file_name,
+                    size,
+                    thumbnail_size,
+                    resampling,
+                )
+            }
+            Self::Path(path) => File::from_path(path.clone(), size, thumbnail_size, resampling),
+        }
+    }
+}
+
+// An SVG whose two folder colors get replaced to create folders in any color
+#[derive(Debug, Clone, PartialEq)]
+pub struct FolderTemplate {
+    pub source: FolderSource,
+    pub primary: HexColor,
+    pub secondary: HexColor,
+}
+
+/* A set of bottom icons. Besides the bundled Adwaita folders, styles can be installed in the folder-styles
+directory of the user data directory. Every style is a directory with a manifest.ini like:
+
+[Folder Style]
+Name=Company Folders
+Description=Folders in the company style
+Preview=preview.svg
+Template=folder.svg
+TemplatePrimary=a4caee
+TemplateSecondary=438de6
+SafeArea=0.35;0.45;0.3;0.3
+
+[Colors]
+Blue=folder_blue.svg
+
+Colors without their own SVG are made from the template */
+#[derive(Debug, Clone, PartialEq)]
+pub struct FolderStyle {
+    // Name of the directory of the style
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub preview: Option<FolderSource>,
+    pub colors: HashMap<String, FolderSource>,
+    pub template: Option<FolderTemplate>,
+    pub safe_area: SafeArea,
+}
+
+impl FolderStyle {
+    pub fn built_in() -> Self {
+        let colors = [
+            "Blue", "Teal", "Green", "Yellow", "Orange", "Red", "Pink", "Purple", "Slate",
+        ]
+        .into_iter()
+        .map(|color| {
+            (
+                color.to_string(),
+                FolderSource::built_in(&format!("folder_{}.svg", color)),
+            )
+        })
+        .collect();
+        Self {
+            id: BUILT_IN_STYLE.to_string(),
+            name: String::from("Adwaita"),
+            description: String::new(),
+            preview: Some(FolderSource::built_in("folder_Blue.svg")),
+            colors,
+            template: Some(FolderTemplate {
+                source: FolderSource::built_in("folder_Custom.svg"),
+                primary: String::from("a4caee"),
+                secondary: String::from("438de6"),
+            }),
+            safe_area: SafeArea::ADWAITA,
+        }
+    }
+
+    pub fn user_directory() -> PathBuf {
+        glib::user_data_dir().join("folder-styles")
+    }
+
+    pub fn from_directory(directory: &Path) -> Result<Self, IconicError> {
+        let invalid = |reason: String| IconicError::InvalidFolderStyle {
+            style: directory.display().to_string(),
+            reason,
+        };
+        let manifest = glib::KeyFile::new();
+        manifest
+            .load_from_file(directory.join(MANIFEST_NAME), glib::KeyFileFlags::NONE)
+            .map_err(|err| invalid(err.to_string()))?;
+        let id = directory
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| invalid(String::from("Folder style directory has no name")))?
+            .to_string();
+        if id == BUILT_IN_STYLE {
+            return Err(invalid(format!(
+                "\"{}\" can not be used as folder style directory name",
+                id
+            )));
+        }
+        let optional_string = |group: &str, key: &str| manifest.string(group, key).ok();
+        let name = manifest
+            .locale_string(STYLE_GROUP, "Name", None)
+            .map(|name| name.to_string())
+            .unwrap_or_else(|_| id.clone());
+        let description = manifest
+            .locale_string(STYLE_GROUP, "Description", None)
+            .map(|description| description.to_string())
+            .unwrap_or_default();
+        let preview = optional_string(STYLE_GROUP, "Preview")
+            .map(|file| FolderSource::Path(directory.join(file)));
+
+        let template = match optional_string(STYLE_GROUP, "Template") {
+            Some(file) => Some(FolderTemplate {
+                source: FolderSource::Path(directory.join(file)),
+                primary: optional_string(STYLE_GROUP, "TemplatePrimary")
+                    .ok_or_else(|| {
+                        invalid(String::from(
+                            "Template is set, but TemplatePrimary is missing",
+                        ))
+                    })?
+                    .trim_start_matches('#')
+                    .to_lowercase(),
+                secondary: optional_string(STYLE_GROUP, "TemplateSecondary")
+                    .ok_or_else(|| {
+                        invalid(String::from(
+                            "Template is set, but TemplateSecondary is missing",
+                        ))
+                    })?
+                    .trim_start_matches('#')
+                    .to_lowercase(),
+            }),
+            None => None,
+        };
+        let mut colors = HashMap::new();
+        if let Ok(keys) = manifest.keys(COLORS_GROUP) {
+            for key in keys.iter() {
+                let file = manifest
+                    .string(COLORS_GROUP, key.as_str())
+                    .map_err(|err| invalid(err.to_string()))?;
+                colors.insert(
+                    key.as_str().to_string(),
+                    FolderSource::Path(directory.join(file.as_str())),
+                );
+            }
+        }
+        if template.is_none() && colors.is_empty() {
+            return Err(invalid(String::from(
+                "Folder style has no template and no colors",
+            )));
+        }
+
+        let safe_area = match manifest.double_list(STYLE_GROUP, "SafeArea") {
+            Ok(values) => match values[..] {
+                [x, y, width, height] if width > 0.0 && height > 0.0 => SafeArea {
+                    x: x as f32,
+                    y: y as f32,
+                    width: width as f32,
+                    height: height as f32,
+                },
+                _ => {
+                    return Err(invalid(String::from(
+                        "SafeArea needs a x, y, width and height",
+                    )))
+                }
+            },
+            Err(_) => SafeArea::ADWAITA,
+        };
+        Ok(Self {
+            id,
+            name,
+            description,
+            preview,
+            colors,
+            template,
+            safe_area,
+        })
+    }
+
+    // The bundled style followed by all styles the user has installed
+    pub fn all() -> Vec<Self> {
+        let mut styles = vec![Self::built_in()];
+        let Ok(entries) = fs::read_dir(Self::user_directory()) else {
+            return styles;
+        };
+        let mut directories: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.join(MANIFEST_NAME).exists())
+            .collect();
+        directories.sort();
+        for directory in directories {
+            match Self::from_directory(&directory) {
+                Ok(style) => styles.push(style),
+                Err(e) => warn!("Could not load folder style {:?}: {}", directory, e),
+            }
+        }
+        styles
+    }
+
+    // Falls back to the bundled style if the style can not be loaded, for example when it has been removed
+    pub fn find(id: &str) -> Self {
+        if id == BUILT_IN_STYLE {
+            return Self::built_in();
+        }
+        match Self::from_directory(&Self::user_directory().join(id)) {
+            Ok(style) => style,
+            Err(e) => {
+                warn!("Could not load folder style {}: {}", id, e);
+                Self::built_in()
+            }
+        }
+    }
+
+    // The SVG made for this color, if the style has one
+    pub fn color_source(&self, color: &str) -> Option<&FolderSource> {
+        self.colors.get(color).filter(|source| source.exists())
+    }
+
+    // Styles without a template use the one of the bundled folders
+    pub fn template_or_built_in(&self) -> Result<FolderTemplate, IconicError> {
+        self.template
+            .clone()
+            .or(Self::built_in().template)
+            .ok_or_else(|| IconicError::InvalidFolderStyle {
+                style: self.id.clone(),
+                reason: String::from("No folder template found"),
+            })
+    }
+
+    // The template as SVG text with its two colors replaced
+    pub fn recolor_template(&self, primary: &str, secondary: &str) -> Result<String, IconicError> {
+        let template = self.template_or_built_in()?;
+        let svg_data = template.source.load()?;
+        let color_mapping = HashMap::from([
+            (template.primary, primary.to_lowercase()),
+            (template.secondary, secondary.to_lowercase()),
+        ]);
+        Ok(RecolorableSvg::from_data(&svg_data)?.recolor(&color_mapping))
+    }
+}
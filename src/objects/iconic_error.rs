@@ -0,0 +1,157 @@
+use gettextrs::gettext;
+use gtk::glib;
+use image::ImageError;
+use resvg::usvg;
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+
+/* Everything that can go wrong while loading images and (re)generating icons.
+Display is written for the log, message and suggestion are shown to the user */
+#[derive(Debug, Clone, PartialEq)]
+pub enum IconicError {
+    // The format that was detected, or the MIME type if nothing was detected
+    UnsupportedFormat(String),
+    // The detected format and what the decoder had to say about it
+    DecodeFailed { format: String, reason: String },
+    // A generated icon whose top image is not in the cache anymore
+    MissingCacheEntry(PathBuf),
+    // The properties stored in the file name of a generated icon could not be read
+    RegenerationParse { file_name: String, reason: String },
+    // The name of an app whose icon could not be found in the icon theme
+    MissingAppIcon(String),
+    // A folder style that could not be loaded or used, by its directory name
+    InvalidFolderStyle { style: String, reason: String },
+    // The generated icon could not be written to where the user wanted it
+    SaveFailed(String),
+    // An accent color name that has no folder color
+    UnknownAccentColor(String),
+    Io(String),
+}
+
+impl fmt::Display for IconicError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedFormat(format) => write!(f, "Unsupported image format: {}", format),
+            Self::DecodeFailed { format, reason } => {
+                write!(f, "Could not decode {} image: {}", format, reason)
+            }
+            Self::MissingCacheEntry(path) => {
+                write!(f, "Top image not found in cache: {}", path.display())
+            }
+            Self::RegenerationParse { file_name, reason } => {
+                write!(
+                    f,
+                    "Could not read properties of \"{}\": {}",
+                    file_name, reason
+                )
+            }
+            Self::MissingAppIcon(app_name) => write!(f, "No icon found for app {}", app_name),
+            Self::InvalidFolderStyle { style, reason } => {
+                write!(f, "Invalid folder style \"{}\": {}", style, reason)
+            }
+            Self::SaveFailed(reason) => write!(f, "Could not save icon: {}", reason),
+            Self::UnknownAccentColor(color) => write!(f, "Unknown accent color: {}", color),
+            Self::Io(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl Error for IconicError {}
+
+impl IconicError {
+    pub fn message(&self) -> String {
+        match self {
+            Self::UnsupportedFormat(format) => {
+                gettext("Images of the type \"{}\" are not supported").replace("{}", format)
+            }
+            Self::DecodeFailed { format, .. } => {
+                gettext("The {} image could not be read, it might be damaged").replace("{}", format)
+            }
+            Self::MissingCacheEntry(_) => {
+                gettext("The top image of a generated icon is missing from the cache")
+            }
+            Self::RegenerationParse { .. } => {
+                gettext("A generated icon could not be regenerated, its file name has been changed")
+            }
+            Self::MissingAppIcon(app_name) => {
+                gettext("The icon of \"{}\" could not be found").replace("{}", app_name)
+            }
+            Self::InvalidFolderStyle { style, .. } => {
+                gettext("The folder style \"{}\" could not be used").replace("{}", style)
+            }
+            Self::SaveFailed(reason) => {
+                format!("{}\n{}", gettext("The icon could not be saved"), reason)
+            }
+            Self::UnknownAccentColor(color) => {
+                gettext("There is no folder in the accent color \"{}\"").replace("{}", color)
+            }
+            Self::Io(reason) => format!("{}\n{}", gettext("The file could not be read"), reason),
+        }
+    }
+
+    pub fn suggestion(&self) -> String {
+        match self {
+            Self::UnsupportedFormat(_) => {
+                gettext("Try converting the image to PNG or SVG and load it again")
+            }
+            Self::DecodeFailed { .. } => {
+                gettext("Try opening the image in another app and exporting it again")
+            }
+            Self::MissingCacheEntry(_) => {
+                gettext("Create the icon again, so it can be regenerated in the future")
+            }
+            Self::RegenerationParse { .. } => {
+                gettext("Keep the file names of generated icons the same, or create the icon again")
+            }
+            Self::MissingAppIcon(_) => {
+                gettext("Try opening the icon file of the app from the icons directory instead")
+            }
+            Self::InvalidFolderStyle { .. } => {
+                gettext("Check the manifest of the folder style, or pick another style")
+            }
+            Self::SaveFailed(_) => gettext("Try saving the icon in another folder"),
+            Self::UnknownAccentColor(_) => {
+                gettext("Pick one of the accent colors in the preferences instead")
+            }
+            Self::Io(_) => {
+                gettext("Make sure the file still exists and Iconic is allowed to read it")
+            }
+        }
+    }
+}
+
+impl From<std::io::Error> for IconicError {
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error.to_string())
+    }
+}
+
+impl From<glib::Error> for IconicError {
+    fn from(error: glib::Error) -> Self {
+        Self::Io(error.to_string())
+    }
+}
+
+impl From<usvg::Error> for IconicError {
+    fn from(error: usvg::Error) -> Self {
+        Self::DecodeFailed {
+            format: String::from("SVG"),
+            reason: error.to_string(),
+        }
+    }
+}
+
+impl IconicError {
+    // Decoding errors of the image crate, for data of which the format is already known
+    pub fn from_image_error(error: ImageError, format: &str) -> Self {
+        match error {
+            ImageError::Unsupported(_) => Self::UnsupportedFormat(format.to_string()),
+            ImageError::IoError(x) => x.into(),
+            x => Self::DecodeFailed {
+                format: format.to_string(),
+                reason: x.to_string(),
+            },
+        }
+    }
+}
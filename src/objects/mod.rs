@@ -1 +1,15 @@
+pub mod app_icon;
+pub mod color_preset;
+pub mod color_profile;
+pub mod container;
+pub mod content_hash;
+pub mod data_file;
 pub mod file;
+pub mod folder_style;
+pub mod iconic_error;
+pub mod palette;
+pub mod pixels;
+pub mod recolor;
+pub mod render_cache;
+pub mod resampling;
+pub mod top_image_cache;
@@ -0,0 +1,137 @@
+use image::{imageops::FilterType, DynamicImage, GenericImageView};
+use log::*;
+
+use crate::objects::recolor::{HexColor, RecolorableSvg};
+
+// The image is made this small before the colors are counted, larger images take long and give the same colors.
+// Nearest is used so no blended colors are created on the edges
+const SAMPLE_SIZE: u32 = 128;
+// Colors closer together than this are seen as the same color
+const MIN_COLOR_DISTANCE: u32 = 40;
+
+/* The most important colors of an image, found by median cut.
+The pixels are split into boxes over and over at the middle of the channel with the largest range,
+until there are enough boxes. Every box then becomes one color, the average of the pixels in it */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    // Best fitting folder color first
+    pub colors: Vec<[u8; 3]>,
+}
+
+impl Palette {
+    pub fn from_image(image: &DynamicImage, count: usize) -> Self {
+        let small_image = image.resize(SAMPLE_SIZE, SAMPLE_SIZE, FilterType::Nearest);
+        // Transparent pixels are not part of the image
+        let pixels: Vec<[u8; 3]> = small_image
+            .pixels()
+            .filter(|(_, _, pixel)| pixel[3] >= 128)
+            .map(|(_, _, pixel)| [pixel[0], pixel[1], pixel[2]])
+            .collect();
+        if pixels.is_empty() {
+            return Self { colors: vec![] };
+        }
+        let total_pixels = pixels.len();
+        let mut boxes = vec![pixels];
+        // More boxes than needed, colors that are almost the same get merged afterwards
+        while boxes.len() < count * 4 {
+            let Some((index, channel)) = boxes
+                .iter()
+                .enumerate()
+                .filter(|(_, pixels)| pixels.len() > 1)
+                .map(|(index, pixels)| {
+                    let (channel, range) = Self::widest_channel(pixels);
+                    (index, channel, range)
+                })
+                .filter(|(_, _, range)| *range > 0)
+                .max_by_key(|(index, _, range)| *range as usize * boxes[*index].len())
+                .map(|(index, channel, _)| (index, channel))
+            else {
+                break;
+            };
+            let mut pixels = boxes.swap_remove(index);
+            pixels.sort_unstable_by_key(|pixel| pixel[channel]);
+            let upper_half = pixels.split_off(pixels.len() / 2);
+            boxes.push(pixels);
+            boxes.push(upper_half);
+        }
+
+        let mut scored_colors: Vec<([u8; 3], f32)> = boxes
+            .iter()
+            .map(|pixels| {
+                let color = Self::average_color(pixels);
+                let share = pixels.len() as f32 / total_pixels as f32;
+                (color, share * Self::folder_suitability(color))
+            })
+            .collect();
+        scored_colors.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        let mut colors: Vec<[u8; 3]> = Vec::with_capacity(count);
+        for (color, _) in scored_colors {
+            if colors.len() == count {
+                break;
+            }
+            if colors
+                .iter()
+                .all(|existing| Self::distance(*existing, color) >= MIN_COLOR_DISTANCE)
+            {
+                colors.push(color);
+            }
+        }
+        debug!("Colors found in image: {:?}", colors);
+        Self { colors }
+    }
+
+    // A primary and secondary folder color for every color of the palette
+    pub fn folder_colors(&self) -> Vec<(HexColor, HexColor)> {
+        self.colors
+            .iter()
+            .filter_map(|color| {
+                RecolorableSvg::derive_folder_colors(&RecolorableSvg::to_hex(
+                    color[0], color[1], color[2],
+                ))
+            })
+            .collect()
+    }
+
+    /* Colorful colors make better folders than the white or black background of a logo,
+    so those count for less. They are still used if the image has nothing else */
+    fn folder_suitability(color: [u8; 3]) -> f32 {
+        let (_, saturation, lightness) = RecolorableSvg::rgb_to_hsl(color);
+        let lightness_penalty = if !(0.1..=0.92).contains(&lightness) {
+            0.2
+        } else {
+            1.0
+        };
+        (0.15 + saturation) * lightness_penalty
+    }
+
+    fn widest_channel(pixels: &[[u8; 3]]) -> (usize, u8) {
+        (0..3)
+            .map(|channel| {
+                let (min, max) = pixels.iter().fold((u8::MAX, u8::MIN), |(min, max), pixel| {
+                    (min.min(pixel[channel]), max.max(pixel[channel]))
+                });
+                (channel, max - min)
+            })
+            .max_by_key(|(_, range)| *range)
+            .unwrap_or((0, 0))
+    }
+
+    fn average_color(pixels: &[[u8; 3]]) -> [u8; 3] {
+        let sum = pixels.iter().fold([0u64; 3], |sum, pixel| {
+            [
+                sum[0] + pixel[0] as u64,
+                sum[1] + pixel[1] as u64,
+                sum[2] + pixel[2] as u64,
+            ]
+        });
+        sum.map(|channel| (channel / pixels.len() as u64) as u8)
+    }
+
+    fn distance(a: [u8; 3], b: [u8; 3]) -> u32 {
+        let squared: u32 = (0..3)
+            .map(|channel| (a[channel] as i32 - b[channel] as i32).pow(2) as u32)
+            .sum();
+        (squared as f32).sqrt() as u32
+    }
+}
@@ -0,0 +1,143 @@
+use image::RgbaImage;
+use once_cell::sync::Lazy;
+use rayon::prelude::*;
+use resvg::tiny_skia::Pixmap;
+
+/* The per-pixel work of rendering, on the raw RGBA bytes. Rows are processed in parallel,
+at a svg-render-size of 2048 an image has four million pixels */
+
+// Linear light values of the 256 sRGB values
+static SRGB_TO_LINEAR: Lazy<[f32; 256]> = Lazy::new(|| {
+    std::array::from_fn(|value| {
+        let value = value as f32 / 255.0;
+        match value <= 0.04045 {
+            true => value / 12.92,
+            false => ((value + 0.055) / 1.055).powf(2.4),
+        }
+    })
+});
+
+// The other way around, with enough steps that every sRGB value can be reached
+const LINEAR_STEPS: usize = 4096;
+static LINEAR_TO_SRGB: Lazy<[u8; LINEAR_STEPS]> = Lazy::new(|| {
+    std::array::from_fn(|step| {
+        let value = step as f32 / (LINEAR_STEPS - 1) as f32;
+        let srgb = match value <= 0.0031308 {
+            true => value * 12.92,
+            false => 1.055 * value.powf(1.0 / 2.4) - 0.055,
+        };
+        (srgb * 255.0).round().clamp(0.0, 255.0) as u8
+    })
+});
+
+fn to_srgb(linear: f32) -> u8 {
+    LINEAR_TO_SRGB[(linear.clamp(0.0, 1.0) * (LINEAR_STEPS - 1) as f32).round() as usize]
+}
+
+/* tiny-skia stores premultiplied RGBA, the image crate straight RGBA. Both use 8 bits per channel
+without padding, so the buffer is moved over as is and only the colors are divided by alpha.
+Without that, semi-transparent edges of SVGs turn dark */
+pub fn pixmap_to_rgba(pixmap: Pixmap) -> RgbaImage {
+    let (width, height) = (pixmap.width(), pixmap.height());
+    let mut image = RgbaImage::from_raw(width, height, pixmap.take()).unwrap();
+    let row_length = width as usize * 4;
+    image.par_chunks_mut(row_length).for_each(|row| {
+        for pixel in row.chunks_exact_mut(4) {
+            demultiply(pixel);
+        }
+    });
+    image
+}
+
+pub fn demultiply(pixel: &mut [u8]) {
+    let alpha = pixel[3] as u32;
+    match alpha {
+        255 => (),
+        0 => pixel[..3].copy_from_slice(&[0, 0, 0]),
+        _ => {
+            for channel in &mut pixel[..3] {
+                *channel = ((*channel as u32 * 255 + alpha / 2) / alpha).min(255) as u8;
+            }
+        }
+    }
+}
+
+/* Puts the top image over the base image at the given position, like imageops::overlay.
+The colors are mixed in linear light with premultiplied alpha, so anti-aliased edges blend into the
+folder instead of getting a dark or light rim */
+pub fn overlay_linear(base: &mut RgbaImage, top: &RgbaImage, x: i64, y: i64) {
+    let (base_width, base_height) = (base.width() as i64, base.height() as i64);
+    let (top_width, top_height) = (top.width() as i64, top.height() as i64);
+    // The part of the base image the top image covers
+    let start_x = x.clamp(0, base_width);
+    let end_x = (x + top_width).clamp(0, base_width);
+    let start_y = y.clamp(0, base_height);
+    let end_y = (y + top_height).clamp(0, base_height);
+    if start_x >= end_x || start_y >= end_y {
+        return;
+    }
+    let base_row_length = base_width as usize * 4;
+    let top_row_length = top_width as usize * 4;
+    let top_data = top.as_raw();
+    base.par_chunks_mut(base_row_length)
+        .enumerate()
+        .skip(start_y as usize)
+        .take((end_y - start_y) as usize)
+        .for_each(|(base_y, base_row)| {
+            let top_y = (base_y as i64 - y) as usize;
+            let top_row = &top_data[top_y * top_row_length..(top_y + 1) * top_row_length];
+            for base_x in start_x..end_x {
+                let top_x = (base_x - x) as usize;
+                let base_x = base_x as usize;
+                blend_pixel(
+                    &mut base_row[base_x * 4..base_x * 4 + 4],
+                    &top_row[top_x * 4..top_x * 4 + 4],
+                );
+            }
+        });
+}
+
+// Source over, on premultiplied linear colors
+pub fn blend_pixel(base: &mut [u8], top: &[u8]) {
+    match top[3] {
+        0 => return,
+        255 => {
+            base.copy_from_slice(top);
+            return;
+        }
+        _ => (),
+    }
+    let top_alpha = top[3] as f32 / 255.0;
+    let base_alpha = base[3] as f32 / 255.0;
+    let alpha = top_alpha + base_alpha * (1.0 - top_alpha);
+    for channel in 0..3 {
+        let top_color = SRGB_TO_LINEAR[top[channel] as usize] * top_alpha;
+        let base_color = SRGB_TO_LINEAR[base[channel] as usize] * base_alpha;
+        let color = top_color + base_color * (1.0 - top_alpha);
+        base[channel] = to_srgb(color / alpha);
+    }
+    base[3] = (alpha * 255.0).round() as u8;
+}
+
+/* Pixels brighter than the threshold get the color, darker ones become transparent.
+The alpha channel is kept, so the edges stay smooth. Invert swaps which pixels get the color.
+Brightness is the luminance of the linear colors, turned back into sRGB so the threshold of grays stays the same */
+pub fn monochrome(mut image: RgbaImage, threshold: u8, color: [u8; 3], invert: bool) -> RgbaImage {
+    let row_length = image.width() as usize * 4;
+    if row_length == 0 {
+        return image;
+    }
+    image.par_chunks_mut(row_length).for_each(|row| {
+        for pixel in row.chunks_exact_mut(4) {
+            let luminance = 0.2126 * SRGB_TO_LINEAR[pixel[0] as usize]
+                + 0.7152 * SRGB_TO_LINEAR[pixel[1] as usize]
+                + 0.0722 * SRGB_TO_LINEAR[pixel[2] as usize];
+            let threshold_reached = to_srgb(luminance) >= threshold && pixel[3] > 0;
+            match threshold_reached != invert {
+                true => pixel[..3].copy_from_slice(&color),
+                false => pixel.copy_from_slice(&[0, 0, 0, 0]),
+            }
+        }
+    });
+    image
+}
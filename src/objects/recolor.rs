@@ -0,0 +1,166 @@
+use hex::FromHex;
+use log::*;
+use resvg::usvg::{Node, Options, Paint, Tree, WriteOptions};
+use std::collections::HashMap;
+
+use crate::objects::iconic_error::IconicError;
+
+// Hex string of a color without the leading '#', in lowercase. "a4caee" for example
+pub type HexColor = String;
+
+/* An SVG that has been rewritten by usvg, so that every color in it is written as a plain "#rrggbb" attribute.
+This makes it possible to recolor any SVG, not only the ones that use the exact same colors as the bundled folders */
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecolorableSvg {
+    pub source: String,
+    // All distinct fill, stroke and gradient stop colors, the most used color first
+    pub colors: Vec<HexColor>,
+}
+
+impl RecolorableSvg {
+    pub fn from_data(svg_data: &[u8]) -> Result<Self, IconicError> {
+        let tree = Tree::from_data(svg_data, &Options::default())?;
+        let mut color_count: HashMap<HexColor, usize> = HashMap::new();
+        Self::count_group_colors(tree.root(), &mut color_count);
+        let gradient_stops = tree
+            .linear_gradients()
+            .iter()
+            .flat_map(|gradient| gradient.stops())
+            .chain(
+                tree.radial_gradients()
+                    .iter()
+                    .flat_map(|gradient| gradient.stops()),
+            );
+        for stop in gradient_stops {
+            let color = stop.color();
+            *color_count
+                .entry(Self::to_hex(color.red, color.green, color.blue))
+                .or_default() += 1;
+        }
+        let mut colors: Vec<(HexColor, usize)> = color_count.into_iter().collect();
+        colors.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        debug!("Colors found in svg: {:?}", colors);
+        Ok(Self {
+            source: tree.to_string(&WriteOptions::default()),
+            colors: colors.into_iter().map(|(color, _)| color).collect(),
+        })
+    }
+
+    // Replaces every color in the mapping in a single pass, so a color that is mapped onto another mapped color is not replaced twice
+    pub fn recolor(&self, mapping: &HashMap<HexColor, HexColor>) -> String {
+        let mut recolored = String::with_capacity(self.source.len());
+        let mut remaining = self.source.as_str();
+        while let Some(index) = remaining.find("=\"#") {
+            let color_start = index + 3;
+            recolored.push_str(&remaining[..color_start]);
+            remaining = &remaining[color_start..];
+            let color = remaining.get(..6).map(|color| color.to_lowercase());
+            match color.as_ref().and_then(|color| mapping.get(color)) {
+                Some(new_color) if remaining[6..].starts_with('"') => {
+                    recolored.push_str(&new_color.to_lowercase());
+                    remaining = &remaining[6..];
+                }
+                _ => (),
+            }
+        }
+        recolored.push_str(remaining);
+        recolored
+    }
+
+    pub fn to_hex(red: u8, green: u8, blue: u8) -> HexColor {
+        format!("{:02x}{:02x}{:02x}", red, green, blue)
+    }
+
+    // Turns the color into one with the hue of the target color, keeping its own lightness.
+    // Colors without saturation, like white highlights and gray shadows, stay the same
+    pub fn shift_hue(color: &str, target: &str) -> HexColor {
+        let (Ok(color), Ok(target)) = (
+            <[u8; 3]>::from_hex(color.trim_start_matches('#')),
+            <[u8; 3]>::from_hex(target.trim_start_matches('#')),
+        ) else {
+            return color.to_lowercase();
+        };
+        let (_, saturation, lightness) = Self::rgb_to_hsl(color);
+        if saturation < 0.1 {
+            return Self::to_hex(color[0], color[1], color[2]);
+        }
+        let (hue, target_saturation, _) = Self::rgb_to_hsl(target);
+        let [red, green, blue] = Self::hsl_to_rgb(hue, target_saturation, lightness);
+        Self::to_hex(red, green, blue)
+    }
+
+    /* Creates a primary (light) and secondary (dark) folder color from a single color.
+    The bundled folders all use the accent color as secondary color, with a lightness of about 40-60%,
+    and the same hue about halfway to white as primary color. Like a4caee and 438de6 for blue */
+    pub fn derive_folder_colors(base_color: &str) -> Option<(HexColor, HexColor)> {
+        let base_color = <[u8; 3]>::from_hex(base_color.trim_start_matches('#')).ok()?;
+        let (hue, saturation, lightness) = Self::rgb_to_hsl(base_color);
+        let secondary_lightness = lightness.clamp(0.38, 0.6);
+        let primary_lightness = ((secondary_lightness + 1.0) / 2.0).clamp(0.72, 0.82);
+        let [red, green, blue] = Self::hsl_to_rgb(hue, saturation, primary_lightness);
+        let primary = Self::to_hex(red, green, blue);
+        let [red, green, blue] = Self::hsl_to_rgb(hue, saturation, secondary_lightness);
+        let secondary = Self::to_hex(red, green, blue);
+        Some((primary, secondary))
+    }
+
+    pub fn rgb_to_hsl(color: [u8; 3]) -> (f32, f32, f32) {
+        let [red, green, blue] = color.map(|channel| channel as f32 / 255.0);
+        let max = red.max(green).max(blue);
+        let min = red.min(green).min(blue);
+        let lightness = (max + min) / 2.0;
+        let delta = max - min;
+        if delta == 0.0 {
+            return (0.0, 0.0, lightness);
+        }
+        let saturation = delta / (1.0 - (2.0 * lightness - 1.0).abs());
+        let hue = if max == red {
+            60.0 * ((green - blue) / delta).rem_euclid(6.0)
+        } else if max == green {
+            60.0 * ((blue - red) / delta + 2.0)
+        } else {
+            60.0 * ((red - green) / delta + 4.0)
+        };
+        (hue, saturation, lightness)
+    }
+
+    pub fn hsl_to_rgb(hue: f32, saturation: f32, lightness: f32) -> [u8; 3] {
+        let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+        let x = chroma * (1.0 - ((hue / 60.0).rem_euclid(2.0) - 1.0).abs());
+        let m = lightness - chroma / 2.0;
+        let (red, green, blue) = match hue.rem_euclid(360.0) {
+            h if h < 60.0 => (chroma, x, 0.0),
+            h if h < 120.0 => (x, chroma, 0.0),
+            h if h < 180.0 => (0.0, chroma, x),
+            h if h < 240.0 => (0.0, x, chroma),
+            h if h < 300.0 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+        [red, green, blue].map(|channel| ((channel + m) * 255.0).round().clamp(0.0, 255.0) as u8)
+    }
+
+    fn count_group_colors(group: &resvg::usvg::Group, color_count: &mut HashMap<HexColor, usize>) {
+        for node in group.children() {
+            match node {
+                Node::Group(group) => Self::count_group_colors(group, color_count),
+                Node::Path(path) => {
+                    let paints = [
+                        path.fill().map(|fill| fill.paint()),
+                        path.stroke().map(|stroke| stroke.paint()),
+                    ];
+                    for paint in paints.into_iter().flatten() {
+                        if let Paint::Color(color) = paint {
+                            *color_count
+                                .entry(Self::to_hex(color.red, color.green, color.blue))
+                                .or_default() += 1;
+                        }
+                    }
+                }
+                Node::Text(text) => Self::count_group_colors(text.flattened(), color_count),
+                Node::Image(_) => (),
+            }
+            // Clip paths, masks and patterns have their own groups
+            node.subroots(|subroot| Self::count_group_colors(subroot, color_count));
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use image::DynamicImage;
+use log::*;
+use std::collections::VecDeque;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::Mutex;
+
+use crate::objects::resampling::Resampling;
+
+// Enough for dragging a slider back and forth without filling the memory with previews
+const CACHE_CAPACITY: usize = 8;
+
+/* Results of the expensive steps of rendering the top image, so moving a slider only redoes what changed.
+Entries are found by a key made from everything the step depends on, the least recently used entry is
+dropped when the cache is full. The lock is not held while an image is created, so saving does not have to
+wait for the preview */
+#[derive(Debug, Default)]
+pub struct RenderCache {
+    monochrome: Mutex<CacheMap>,
+    transformed: Mutex<CacheMap>,
+}
+
+impl RenderCache {
+    // Key of the top image after the monochrome filter, by (hash, threshold, color, invert)
+    pub fn monochrome_key(source: u64, threshold: u8, color: [u8; 3], invert: bool) -> u64 {
+        Self::key(("monochrome", source, threshold, color, invert))
+    }
+
+    /* Key of the resized, flipped and rotated top image, by (hash, size, resampling).
+    SVGs are rendered without resampling, so theirs is none */
+    pub fn transformed_key(
+        source: u64,
+        size: (u32, u32),
+        resampling: Option<Resampling>,
+        flip: (bool, bool),
+        rotation: f32,
+    ) -> u64 {
+        Self::key((
+            "transformed",
+            source,
+            size,
+            resampling,
+            flip,
+            rotation.to_bits(),
+        ))
+    }
+
+    pub fn monochrome<F>(&self, key: u64, create: F) -> DynamicImage
+    where
+        F: FnOnce() -> DynamicImage,
+    {
+        Self::get_or_insert(&self.monochrome, key, create)
+    }
+
+    pub fn transformed<F>(&self, key: u64, create: F) -> DynamicImage
+    where
+        F: FnOnce() -> DynamicImage,
+    {
+        Self::get_or_insert(&self.transformed, key, create)
+    }
+
+    fn get_or_insert<F>(map: &Mutex<CacheMap>, key: u64, create: F) -> DynamicImage
+    where
+        F: FnOnce() -> DynamicImage,
+    {
+        if let Some(image) = map.lock().unwrap().get(key) {
+            return image;
+        }
+        trace!("Render cache miss for {}", key);
+        let image = create();
+        map.lock().unwrap().insert(key, image.clone());
+        image
+    }
+
+    fn key<T: Hash>(value: T) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+// The most recently used entry is at the back
+#[derive(Debug, Default)]
+struct CacheMap {
+    entries: VecDeque<(u64, DynamicImage)>,
+}
+
+impl CacheMap {
+    fn get(&mut self, key: u64) -> Option<DynamicImage> {
+        let index = self
+            .entries
+            .iter()
+            .position(|(entry_key, _)| *entry_key == key)?;
+        let entry = self.entries.remove(index)?;
+        let image = entry.1.clone();
+        self.entries.push_back(entry);
+        Some(image)
+    }
+
+    fn insert(&mut self, key: u64, image: DynamicImage) {
+        self.entries.retain(|(entry_key, _)| *entry_key != key);
+        if self.entries.len() >= CACHE_CAPACITY {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((key, image));
+    }
+}
@@ -0,0 +1,72 @@
+use gettextrs::gettext;
+use image::{imageops::FilterType, DynamicImage, GenericImageView};
+
+/* How the top image is resized, set in the preferences and used for the preview, saved icons and thumbnails.
+Pixel art only scales by whole numbers with nearest neighbour, so every pixel stays a sharp square.
+The top image can end up a bit smaller than the size slider says because of that */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Resampling {
+    Lanczos3,
+    CatmullRom,
+    PixelArt,
+}
+
+impl Resampling {
+    // In the order of the preferences
+    pub const ALL: [Resampling; 3] = [Self::Lanczos3, Self::CatmullRom, Self::PixelArt];
+
+    // As stored in the resampling-filter setting
+    pub fn id(&self) -> &'static str {
+        match self {
+            Self::Lanczos3 => "lanczos3",
+            Self::CatmullRom => "catmull-rom",
+            Self::PixelArt => "pixel-art",
+        }
+    }
+
+    // Unknown ids fall back to Lanczos3, the sharpest filter for logos
+    pub fn from_id(id: &str) -> Self {
+        Self::ALL
+            .into_iter()
+            .find(|resampling| resampling.id() == id)
+            .unwrap_or(Self::Lanczos3)
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Self::Lanczos3 => gettext("Lanczos"),
+            Self::CatmullRom => gettext("Catmull-Rom"),
+            Self::PixelArt => gettext("Pixel art"),
+        }
+    }
+
+    // Fits the image in a box of the given size, keeping the aspect ratio like DynamicImage::resize
+    pub fn resize(&self, image: &DynamicImage, width: u32, height: u32) -> DynamicImage {
+        match self {
+            Self::Lanczos3 => image.resize(width, height, FilterType::Lanczos3),
+            Self::CatmullRom => image.resize(width, height, FilterType::CatmullRom),
+            Self::PixelArt => Self::integer_scale(image, width, height),
+        }
+    }
+
+    fn integer_scale(image: &DynamicImage, width: u32, height: u32) -> DynamicImage {
+        let (image_width, image_height) = image.dimensions();
+        if image_width == 0 || image_height == 0 {
+            return image.clone();
+        }
+        // The largest whole factor that fits, or the smallest whole divisor if the image is too large
+        let (new_width, new_height) = match (width / image_width).min(height / image_height) {
+            0 => {
+                let divisor = image_width
+                    .div_ceil(width.max(1))
+                    .max(image_height.div_ceil(height.max(1)));
+                (
+                    (image_width / divisor).max(1),
+                    (image_height / divisor).max(1),
+                )
+            }
+            factor => (image_width * factor, image_height * factor),
+        };
+        image.resize_exact(new_width, new_height, FilterType::Nearest)
+    }
+}
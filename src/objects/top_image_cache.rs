@@ -0,0 +1,302 @@
+use gtk::glib;
+use log::*;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::objects::content_hash::PerceptualHash;
+
+pub const TOP_IMAGE_DIRECTORY: &str = "top_images";
+/* Kept next to the top images, with a group for every cached top image by name:
+
+[<content hash>]
+Name=logo.svg
+LastUsed=1733000000
+PerceptualHash=0313130313130303
+Pinned=true
+Properties=0;0;-5;... (the properties in the file names of generated icons, after folder_new)
+Kind=bottom (only for custom bottom images, which are cached so icons with them can be regenerated too)
+*/
+const INDEX_NAME: &str = "index.ini";
+const NAME_KEY: &str = "Name";
+const LAST_USED_KEY: &str = "LastUsed";
+const PERCEPTUAL_HASH_KEY: &str = "PerceptualHash";
+const PINNED_KEY: &str = "Pinned";
+const PROPERTIES_KEY: &str = "Properties";
+const KIND_KEY: &str = "Kind";
+const BOTTOM_KIND: &str = "bottom";
+/* Generated icons start with this, their last property is the name of their top image. Icons with a custom
+bottom image name it in the property before */
+const GENERATED_ICON_PREFIX: &str = "folder_new-";
+// Number of properties of icons that can name a bottom image, including folder_new and the top image
+const BOTTOM_IMAGE_PROPERTIES: usize = 26;
+
+// A cached top image, as listed in the cache browser
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheEntry {
+    // The content hash of the top image, which is also its file name
+    pub name: String,
+    pub path: PathBuf,
+    // The name of the file the top image was loaded from, unknown for images cached by older versions
+    pub original_name: Option<String>,
+    pub size: u64,
+    // Seconds since the unix epoch
+    pub last_used: i64,
+    // Generated icons that need this top image to be regenerated
+    pub dependents: Vec<PathBuf>,
+    // Pinned top images stay in the gallery of the welcome page and are never removed by the quota
+    pub pinned: bool,
+    // The properties the top image was last saved with, to load it again the same way
+    pub properties: Option<Vec<String>>,
+    // Custom bottom images are cached next to the top images, but are not shown as top images
+    pub bottom: bool,
+}
+
+/* The top images that are stored so generated icons can be regenerated in another color.
+Only the files themselves are needed for regeneration, the index adds what the cache browser shows.
+Changes to the index are written by save */
+pub struct TopImageCache {
+    directory: PathBuf,
+    index: glib::KeyFile,
+}
+
+impl TopImageCache {
+    // Cache path is the cache directory of iconic, the top images are in a directory inside it
+    pub fn open(cache_path: &Path) -> Self {
+        let directory = cache_path.join(TOP_IMAGE_DIRECTORY);
+        let index = glib::KeyFile::new();
+        if let Err(err) = index.load_from_file(directory.join(INDEX_NAME), glib::KeyFileFlags::NONE)
+        {
+            debug!("No top image index loaded: {}", err);
+        }
+        Self { directory, index }
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    pub fn entry_path(&self, name: &str) -> PathBuf {
+        self.directory.join(name)
+    }
+
+    // Adds a top image that was just stored to the index
+    pub fn record(&self, name: &str, original_name: &str, perceptual_hash: Option<PerceptualHash>) {
+        self.index.set_string(name, NAME_KEY, original_name);
+        if let Some(perceptual_hash) = perceptual_hash {
+            self.index
+                .set_string(name, PERCEPTUAL_HASH_KEY, &perceptual_hash.to_hex());
+        }
+        self.touch(name);
+    }
+
+    // Marks the top image as used now, which keeps it in the cache longest when the quota is reached
+    pub fn touch(&self, name: &str) {
+        self.index.set_int64(name, LAST_USED_KEY, Self::now());
+    }
+
+    pub fn set_pinned(&self, name: &str, pinned: bool) {
+        self.index.set_boolean(name, PINNED_KEY, pinned);
+    }
+
+    // Remembers the position and monochrome settings the top image was last saved with
+    pub fn set_properties(&self, name: &str, properties: &[String]) {
+        /* Written as one string in the list format of key files, separated by ';', because glib 0.20 has
+        no binding for set_string_list. It is read back with string_list */
+        self.index
+            .set_string(name, PROPERTIES_KEY, &properties.join(";"));
+    }
+
+    // Bottom images are left out of the gallery and the cache browser
+    pub fn set_bottom(&self, name: &str, bottom: bool) {
+        if bottom {
+            self.index.set_string(name, KIND_KEY, BOTTOM_KIND);
+        } else {
+            let _ = self.index.remove_key(name, KIND_KEY);
+        }
+    }
+
+    // Names of the cached top images that look like the given one, except the one with the given name
+    pub fn near_duplicates(&self, name: &str, perceptual_hash: PerceptualHash) -> Vec<String> {
+        self.index
+            .groups()
+            .iter()
+            .map(|group| group.as_str().to_string())
+            .filter(|group| group != name)
+            .filter(|group| {
+                self.index
+                    .string(group, PERCEPTUAL_HASH_KEY)
+                    .ok()
+                    .and_then(|hex| PerceptualHash::from_hex(&hex))
+                    .is_some_and(|cached| cached.is_near_duplicate(&perceptual_hash))
+            })
+            .collect()
+    }
+
+    /* Every cached image, including bottom images, most recently used first. Icons directory is where the generated icons
+    are stored, they are searched for the top images they depend on */
+    pub fn entries(&self, icons_directory: &Path) -> Vec<CacheEntry> {
+        let Ok(files) = fs::read_dir(&self.directory) else {
+            return vec![];
+        };
+        let mut dependents = Self::dependents(icons_directory);
+        let mut entries: Vec<CacheEntry> = files
+            .filter_map(|file| file.ok())
+            .filter(|file| file.file_name() != INDEX_NAME)
+            // Temporary files of writes that did not finish start with a dot
+            .filter(|file| !file.file_name().to_string_lossy().starts_with('.'))
+            .filter_map(|file| {
+                let metadata = file.metadata().ok()?;
+                if !metadata.is_file() {
+                    return None;
+                }
+                let name = file.file_name().to_string_lossy().to_string();
+                // Images cached before the index existed were last used when they were stored
+                let last_used = self
+                    .index
+                    .int64(&name, LAST_USED_KEY)
+                    .ok()
+                    .or_else(|| Self::seconds(metadata.modified().ok()?))
+                    .unwrap_or(0);
+                Some(CacheEntry {
+                    original_name: self
+                        .index
+                        .string(&name, NAME_KEY)
+                        .ok()
+                        .map(|name| name.to_string()),
+                    size: metadata.len(),
+                    last_used,
+                    dependents: dependents.remove(&name).unwrap_or_default(),
+                    pinned: self.index.boolean(&name, PINNED_KEY).unwrap_or(false),
+                    properties: self.index.string_list(&name, PROPERTIES_KEY).ok().map(
+                        |properties| properties.iter().map(|value| value.to_string()).collect(),
+                    ),
+                    bottom: self
+                        .index
+                        .string(&name, KIND_KEY)
+                        .is_ok_and(|kind| kind == BOTTOM_KIND),
+                    path: file.path(),
+                    name,
+                })
+            })
+            .collect();
+        entries.sort_by_key(|entry| Reverse(entry.last_used));
+        entries
+    }
+
+    // The cached top images without the bottom images, as listed in the cache browser
+    pub fn top_entries(&self, icons_directory: &Path) -> Vec<CacheEntry> {
+        self.entries(icons_directory)
+            .into_iter()
+            .filter(|entry| !entry.bottom)
+            .collect()
+    }
+
+    // What the welcome page shows: every pinned top image, then the most recently used ones up to the limit
+    pub fn gallery(&self, icons_directory: &Path, limit: usize) -> Vec<CacheEntry> {
+        let (mut gallery, recent): (Vec<CacheEntry>, Vec<CacheEntry>) = self
+            .top_entries(icons_directory)
+            .into_iter()
+            .partition(|entry| entry.pinned);
+        let remaining = limit.saturating_sub(gallery.len());
+        gallery.extend(recent.into_iter().take(remaining));
+        gallery
+    }
+
+    pub fn remove(&self, name: &str) -> std::io::Result<()> {
+        let path = self.entry_path(name);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        let _ = self.index.remove_group(name);
+        Ok(())
+    }
+
+    /* Removes the least recently used top images until the cache fits in the quota, in MB like the
+    cache size in the preferences. A quota of 0 is no limit. Top images that generated icons depend on
+    and pinned ones are kept, even if that means the cache stays too large. Returns the names of the removed top images */
+    pub fn enforce_quota(&self, quota_megabytes: i32, icons_directory: &Path) -> Vec<String> {
+        if quota_megabytes <= 0 {
+            return vec![];
+        }
+        let quota = quota_megabytes as u64 * 1_000_000;
+        let entries = self.entries(icons_directory);
+        let mut size: u64 = entries.iter().map(|entry| entry.size).sum();
+        let mut removed = vec![];
+        for entry in entries.iter().rev() {
+            if size <= quota {
+                break;
+            }
+            if entry.pinned || !entry.dependents.is_empty() {
+                continue;
+            }
+            match self.remove(&entry.name) {
+                Ok(()) => {
+                    size -= entry.size;
+                    removed.push(entry.name.clone());
+                }
+                Err(err) => warn!("Could not remove cached top image {}: {}", entry.name, err),
+            }
+        }
+        if size > quota {
+            info!(
+                "Top image cache is {} bytes, over the quota of {}, but the rest is still needed",
+                size, quota
+            );
+        }
+        removed
+    }
+
+    pub fn save(&self) {
+        if !self.directory.exists() {
+            return;
+        }
+        if let Err(err) = self.index.save_to_file(self.directory.join(INDEX_NAME)) {
+            warn!("Could not save top image index: {}", err);
+        }
+    }
+
+    // Generated icons by the name of their top image, and of their bottom image if it is cached as well
+    fn dependents(icons_directory: &Path) -> HashMap<String, Vec<PathBuf>> {
+        let mut dependents: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        let Ok(files) = fs::read_dir(icons_directory) else {
+            return dependents;
+        };
+        for file in files.filter_map(|file| file.ok()) {
+            let file_name = file.file_name().to_string_lossy().to_string();
+            if !file_name.starts_with(GENERATED_ICON_PREFIX) {
+                continue;
+            }
+            let mut properties = file_name.rsplit('-');
+            let Some(name) = properties.next().and_then(|last| last.split('.').next()) else {
+                continue;
+            };
+            dependents
+                .entry(name.to_string())
+                .or_default()
+                .push(file.path());
+            if file_name.split('-').count() < BOTTOM_IMAGE_PROPERTIES {
+                continue;
+            }
+            if let Some(bottom) = properties.next().filter(|bottom| *bottom != "none") {
+                dependents
+                    .entry(bottom.to_string())
+                    .or_default()
+                    .push(file.path());
+            }
+        }
+        dependents
+    }
+
+    fn now() -> i64 {
+        Self::seconds(SystemTime::now()).unwrap_or(0)
+    }
+
+    fn seconds(time: SystemTime) -> Option<i64> {
+        time.duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|duration| duration.as_secs() as i64)
+    }
+}
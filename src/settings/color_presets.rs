@@ -0,0 +1,278 @@
+use adw::prelude::{
+    AlertDialogExt, AlertDialogExtManual, ComboRowExt, EntryRowExt, PreferencesGroupExt,
+};
+use gettextrs::gettext;
+use gio::{
+    prelude::{ListModelExt, SettingsExt},
+    subclass::prelude::ObjectSubclassIsExt,
+};
+use gtk::{
+    glib,
+    prelude::{BoxExt, ButtonExt, CastNone, EditableExt, WidgetExt},
+};
+use log::*;
+
+use crate::objects::color_preset::ColorPreset;
+use crate::objects::folder_style::FolderStyle;
+use crate::settings::settings::{PreferencesDialog, FOLDER_COLORS};
+
+const PREVIEW_SIZE: u32 = 32;
+
+impl PreferencesDialog {
+    /* Creates a row for every color preset and adds the presets to the folder color dropdown,
+    after the built-in colors */
+    pub fn load_color_presets(&self) {
+        let imp = self.imp();
+        let presets = ColorPreset::load_all(&imp.settings);
+        for row in imp.color_preset_rows.take() {
+            imp.color_presets_group.remove(&row);
+        }
+        let folder_style = FolderStyle::find(&imp.settings.string("folder-style"));
+        for (index, preset) in presets.iter().enumerate() {
+            let is_last = index + 1 == presets.len();
+            let row = self.create_color_preset_row(index, is_last, preset, &folder_style);
+            imp.color_presets_group.add(&row);
+            imp.color_preset_rows.borrow_mut().push(row);
+        }
+
+        // Changing the list would otherwise select a different color
+        imp.updating_color_list.set(true);
+        if let Some(model) = imp
+            .select_bottom_color
+            .model()
+            .and_downcast::<gtk::StringList>()
+        {
+            let preset_names: Vec<&str> =
+                presets.iter().map(|preset| preset.name.as_str()).collect();
+            model.splice(
+                FOLDER_COLORS.len() as u32,
+                model.n_items() - FOLDER_COLORS.len() as u32,
+                &preset_names,
+            );
+        }
+        imp.select_bottom_color
+            .set_selected(self.selected_color_index(&presets));
+        imp.updating_color_list.set(false);
+    }
+
+    fn selected_color_index(&self, presets: &[ColorPreset]) -> u32 {
+        let imp = self.imp();
+        let selected_color = imp.settings.string("selected-accent-color").to_string();
+        if let Some(index) = presets
+            .iter()
+            .position(|preset| preset.selected_value() == selected_color)
+        {
+            return (FOLDER_COLORS.len() + index) as u32;
+        }
+        match FOLDER_COLORS
+            .iter()
+            .position(|color| *color == selected_color)
+        {
+            Some(index) => index as u32,
+            // The system accent color is used, keep showing the color that was selected before
+            None => (imp.settings.int("selected-accent-color-index") as u32)
+                .min(FOLDER_COLORS.len() as u32 - 1),
+        }
+    }
+
+    fn create_color_preset_row(
+        &self,
+        index: usize,
+        is_last: bool,
+        preset: &ColorPreset,
+        folder_style: &FolderStyle,
+    ) -> adw::EntryRow {
+        let row = adw::EntryRow::builder()
+            .title(gettext("Name"))
+            .text(&preset.name)
+            .show_apply_button(true)
+            .build();
+        match folder_style
+            .recolor_template(&preset.primary, &preset.secondary)
+            .ok()
+            .and_then(|svg| Self::render_preview(&svg, PREVIEW_SIZE).ok())
+        {
+            Some(texture) => {
+                let image = gtk::Image::from_paintable(Some(&texture));
+                image.set_pixel_size(PREVIEW_SIZE as i32);
+                row.add_prefix(&image);
+            }
+            None => warn!("Could not create preview of color preset {}", preset.name),
+        };
+
+        let buttons = gtk::Box::builder().valign(gtk::Align::Center).build();
+        buttons.add_css_class("linked");
+        let move_up = gtk::Button::builder()
+            .icon_name("go-up-symbolic")
+            .tooltip_text(gettext("Move Up"))
+            .sensitive(index > 0)
+            .build();
+        let move_down = gtk::Button::builder()
+            .icon_name("go-down-symbolic")
+            .tooltip_text(gettext("Move Down"))
+            .sensitive(!is_last)
+            .build();
+        let overwrite = gtk::Button::builder()
+            .icon_name("document-save-symbolic")
+            .tooltip_text(gettext("Replace With Current Custom Colors"))
+            .build();
+        let delete = gtk::Button::builder()
+            .icon_name("user-trash-symbolic")
+            .tooltip_text(gettext("Delete"))
+            .build();
+        for button in [&move_up, &move_down, &overwrite, &delete] {
+            buttons.append(button);
+        }
+        row.add_suffix(&buttons);
+
+        let id = preset.id.clone();
+        row.connect_apply(glib::clone!(
+            #[weak (rename_to = this)]
+            self,
+            #[strong]
+            id,
+            move |row| {
+                this.update_color_preset(&id, |presets, index| {
+                    presets[index].name = row.text().to_string();
+                });
+            }
+        ));
+        move_up.connect_clicked(glib::clone!(
+            #[weak (rename_to = this)]
+            self,
+            #[strong]
+            id,
+            move |_| {
+                this.update_color_preset(&id, |presets, index| presets.swap(index, index - 1));
+            }
+        ));
+        move_down.connect_clicked(glib::clone!(
+            #[weak (rename_to = this)]
+            self,
+            #[strong]
+            id,
+            move |_| {
+                this.update_color_preset(&id, |presets, index| presets.swap(index, index + 1));
+            }
+        ));
+        overwrite.connect_clicked(glib::clone!(
+            #[weak (rename_to = this)]
+            self,
+            #[strong]
+            id,
+            move |_| {
+                let settings = &this.imp().settings;
+                let primary = settings.string("primary-folder-color").to_lowercase();
+                let secondary = settings.string("secondary-folder-color").to_lowercase();
+                this.update_color_preset(&id, |presets, index| {
+                    presets[index].primary = primary;
+                    presets[index].secondary = secondary;
+                });
+            }
+        ));
+        delete.connect_clicked(glib::clone!(
+            #[weak (rename_to = this)]
+            self,
+            #[strong]
+            id,
+            move |_| {
+                this.delete_color_preset(&id);
+            }
+        ));
+        row
+    }
+
+    fn update_color_preset<F>(&self, id: &str, change: F)
+    where
+        F: FnOnce(&mut Vec<ColorPreset>, usize),
+    {
+        let imp = self.imp();
+        let mut presets = ColorPreset::load_all(&imp.settings);
+        let Some(index) = presets.iter().position(|preset| preset.id == id) else {
+            return;
+        };
+        change(&mut presets, index);
+        self.can_error(ColorPreset::store_all(&imp.settings, &presets).map_err(|e| e.into()));
+        self.load_color_presets();
+    }
+
+    fn delete_color_preset(&self, id: &str) {
+        let imp = self.imp();
+        // Keep the folder the same when the preset in use is deleted, by turning it into the custom colors
+        if let Some(preset) = ColorPreset::selected(&imp.settings).filter(|preset| preset.id == id)
+        {
+            let _ = imp
+                .settings
+                .set_string("primary-folder-color", &preset.primary.to_uppercase());
+            let _ = imp
+                .settings
+                .set_string("secondary-folder-color", &preset.secondary.to_uppercase());
+            let _ = imp.settings.set_string("selected-accent-color", "Custom");
+            let _ = imp.settings.set_int(
+                "selected-accent-color-index",
+                FOLDER_COLORS.len() as i32 - 1,
+            );
+            self.load_set_colors();
+        }
+        let mut presets = ColorPreset::load_all(&imp.settings);
+        presets.retain(|preset| preset.id != id);
+        self.can_error(ColorPreset::store_all(&imp.settings, &presets).map_err(|e| e.into()));
+        self.load_color_presets();
+        self.show_color_options();
+    }
+
+    // Asks for a name and stores the current custom colors as a new preset
+    pub async fn save_color_preset(&self) {
+        const RESPONSE_CANCEL: &str = "cancel";
+        const RESPONSE_SAVE: &str = "save";
+        let imp = self.imp();
+        let name_entry = gtk::Entry::builder()
+            .placeholder_text(gettext("Preset Name"))
+            .activates_default(true)
+            .build();
+        let dialog = adw::AlertDialog::builder()
+            .heading(gettext("Save Color Preset"))
+            .body(gettext(
+                "The current custom folder colors are saved under this name",
+            ))
+            .extra_child(&name_entry)
+            .close_response(RESPONSE_CANCEL)
+            .default_response(RESPONSE_SAVE)
+            .build();
+        dialog.add_response(RESPONSE_CANCEL, &gettext("Cancel"));
+        dialog.add_response(RESPONSE_SAVE, &gettext("Save"));
+        dialog.set_response_appearance(RESPONSE_SAVE, adw::ResponseAppearance::Suggested);
+        dialog.set_response_enabled(RESPONSE_SAVE, false);
+        name_entry.connect_changed(glib::clone!(
+            #[weak]
+            dialog,
+            move |entry| {
+                dialog.set_response_enabled(RESPONSE_SAVE, !entry.text().trim().is_empty());
+            }
+        ));
+
+        if &*dialog.clone().choose_future(self).await != RESPONSE_SAVE {
+            return;
+        }
+        let preset = ColorPreset::new(
+            name_entry.text().trim(),
+            imp.settings.string("primary-folder-color").into(),
+            imp.settings.string("secondary-folder-color").into(),
+        );
+        let mut presets = ColorPreset::load_all(&imp.settings);
+        presets.push(preset.clone());
+        self.can_error(ColorPreset::store_all(&imp.settings, &presets).map_err(|e| e.into()));
+        // Use the new preset right away, so icons made from now on follow it
+        if !imp.use_system_color.is_active() {
+            let _ = imp
+                .settings
+                .set_string("selected-accent-color", &preset.selected_value());
+            let _ = imp.settings.set_int(
+                "selected-accent-color-index",
+                (FOLDER_COLORS.len() + presets.len() - 1) as i32,
+            );
+        }
+        self.load_color_presets();
+        self.show_color_options();
+    }
+}
@@ -1,8 +1,24 @@
-use gio::{prelude::SettingsExt, subclass::prelude::ObjectSubclassIsExt};
-use gtk::{gdk::RGBA, prelude::WidgetExt};
+use adw::prelude::{ActionRowExt, PreferencesGroupExt};
+use gio::{
+    prelude::{SettingsExt, SettingsExtManual},
+    subclass::prelude::ObjectSubclassIsExt,
+};
+use gtk::{
+    gdk::{self, RGBA},
+    glib,
+    prelude::{Cast, CheckButtonExt, WidgetExt},
+};
 use hex::FromHex;
 use log::*;
+use resvg::tiny_skia::{Pixmap, Transform};
+use resvg::usvg::{Options, Tree};
+use std::collections::HashMap;
+use std::fs;
 
+use crate::objects::file::SourceFormat;
+use crate::objects::folder_style::FolderStyle;
+use crate::objects::iconic_error::IconicError;
+use crate::objects::recolor::{HexColor, RecolorableSvg};
 use crate::{settings::settings::PreferencesDialog, window::GtkTestWindow};
 
 impl PreferencesDialog {
@@ -13,6 +29,9 @@ impl PreferencesDialog {
         // imp.reveal_custom_colors.set_reveal_child(color_setting);
         imp.primary_color_row.set_visible(color_setting);
         imp.secondary_color_row.set_visible(color_setting);
+        imp.derive_color_row.set_visible(color_setting);
+        imp.derive_preview_row
+            .set_visible(color_setting && imp.derived_colors.borrow().is_some());
     }
 
     pub fn rgba_to_hex(&self, rgba: RGBA) -> String {
@@ -29,4 +48,122 @@ impl PreferencesDialog {
         let decoded = <[u8; 3]>::from_hex(hex).unwrap_or([255, 255, 255]);
         GtkTestWindow::to_rgba(decoded[0], decoded[1], decoded[2])
     }
+
+    // Shows what the custom folder would look like with colors derived from the picked color, without applying them yet
+    pub fn preview_derived_colors(&self) {
+        let imp = self.imp();
+        let base_color = self.rgba_to_hex(imp.derive_folder_color.rgba());
+        let Some((primary, secondary)) = RecolorableSvg::derive_folder_colors(&base_color) else {
+            return;
+        };
+        let preview = FolderStyle::find(&imp.settings.string("folder-style"))
+            .recolor_template(&primary, &secondary)
+            .and_then(|svg| Self::render_preview(&svg, 64));
+        match preview {
+            Ok(texture) => imp.derive_preview.set_paintable(Some(&texture)),
+            Err(e) => warn!("Could not create preview of derived colors: {}", e),
+        };
+        imp.derived_colors.replace(Some((primary, secondary)));
+        self.show_color_options();
+    }
+
+    pub fn apply_derived_colors(&self) {
+        let imp = self.imp();
+        let Some((primary, secondary)) = imp.derived_colors.take() else {
+            return;
+        };
+        // Setting the buttons also stores the colors in the settings
+        imp.primary_folder_color
+            .set_rgba(&PreferencesDialog::hex_to_rgba(primary));
+        imp.secondary_folder_color
+            .set_rgba(&PreferencesDialog::hex_to_rgba(secondary));
+        self.show_color_options();
+    }
+
+    pub fn render_preview(svg: &str, size: u32) -> Result<gdk::Texture, IconicError> {
+        let tree = Tree::from_str(svg, &Options::default())?;
+        let scale = size as f32 / tree.size().width().max(tree.size().height());
+        let mut pixmap = Pixmap::new(size, size).ok_or_else(|| IconicError::DecodeFailed {
+            format: String::from("SVG"),
+            reason: format!("Could not create a preview of {}x{} pixels", size, size),
+        })?;
+        resvg::render(
+            &tree,
+            Transform::from_scale(scale, scale),
+            &mut pixmap.as_mut(),
+        );
+        // tiny-skia stores premultiplied pixels, which GTK can use directly
+        Ok(gdk::MemoryTexture::new(
+            size as i32,
+            size as i32,
+            gdk::MemoryFormat::R8g8b8a8Premultiplied,
+            &glib::Bytes::from(pixmap.data()),
+            size as usize * 4,
+        )
+        .upcast())
+    }
+
+    // Lists every color found in the custom bottom SVG, with a button to pick the color it should be replaced with
+    pub fn load_bottom_colors(&self) {
+        let imp = self.imp();
+        for row in imp.bottom_color_rows.take() {
+            imp.bottom_colors_group.remove(&row);
+        }
+        let bottom_path = self
+            .get_cache_path()
+            .join(imp.settings.string("folder-cache-name"));
+        let svg = fs::read(&bottom_path)
+            .ok()
+            .filter(|data| SourceFormat::detect(data).is_some_and(|format| format.is_svg()))
+            .and_then(|svg_data| RecolorableSvg::from_data(&svg_data).ok());
+        imp.bottom_colors_group
+            .set_visible(imp.use_external_icon_button.is_active() && svg.is_some());
+        let Some(svg) = svg else {
+            debug!("Custom bottom icon is not a recolorable svg");
+            return;
+        };
+        let color_mapping: HashMap<HexColor, HexColor> = imp.settings.get("bottom-color-map");
+        for color in svg.colors {
+            let current_color = color_mapping.get(&color).unwrap_or(&color);
+            let color_button = gtk::ColorDialogButton::builder()
+                .valign(gtk::Align::Center)
+                .rgba(&PreferencesDialog::hex_to_rgba(current_color.clone()))
+                .dialog(&gtk::ColorDialog::builder().with_alpha(false).build())
+                .build();
+            let row = adw::ActionRow::builder()
+                .title(format!("#{}", color))
+                .activatable_widget(&color_button)
+                .build();
+            row.add_suffix(&color_button);
+            color_button.connect_rgba_notify(glib::clone!(
+                #[weak (rename_to = this)]
+                self,
+                move |button| {
+                    this.set_bottom_color(&color, button.rgba());
+                }
+            ));
+            imp.bottom_colors_group.add(&row);
+            imp.bottom_color_rows.borrow_mut().push(row);
+        }
+    }
+
+    fn set_bottom_color(&self, original_color: &str, rgba: RGBA) {
+        let imp = self.imp();
+        let mut color_mapping: HashMap<HexColor, HexColor> = imp.settings.get("bottom-color-map");
+        let new_color = self.rgba_to_hex(rgba).to_lowercase();
+        if new_color == original_color {
+            color_mapping.remove(original_color);
+        } else {
+            color_mapping.insert(original_color.to_string(), new_color);
+        }
+        let _ = imp.settings.set("bottom-color-map", color_mapping);
+    }
+
+    pub fn reset_bottom_colors(&self) {
+        let _ = self
+            .imp()
+            .settings
+            .set("bottom-color-map", HashMap::<HexColor, HexColor>::new());
+        self.load_bottom_colors();
+    }
 }
@@ -0,0 +1,70 @@
+use adw::prelude::{ActionRowExt, ComboRowExt};
+use gio::{prelude::SettingsExt, subclass::prelude::ObjectSubclassIsExt};
+use gtk::prelude::{CastNone, WidgetExt};
+use log::*;
+use std::fs;
+
+use crate::objects::folder_style::{FolderSource, FolderStyle};
+use crate::settings::settings::PreferencesDialog;
+
+impl PreferencesDialog {
+    pub fn load_folder_styles(&self) {
+        let imp = self.imp();
+        let styles = FolderStyle::all();
+        let current_style = imp.settings.string("folder-style");
+        let style_names: Vec<&str> = styles.iter().map(|style| style.name.as_str()).collect();
+        let selected = styles
+            .iter()
+            .position(|style| style.id == current_style.as_str())
+            .unwrap_or(0);
+        imp.folder_style_row
+            .set_model(Some(&gtk::StringList::new(&style_names)));
+        imp.folder_style_row.set_selected(selected as u32);
+        imp.folder_styles.replace(styles);
+        self.show_folder_style_preview();
+    }
+
+    pub fn folder_style_selected(&self) {
+        let imp = self.imp();
+        let selected = imp.folder_style_row.selected() as usize;
+        let Some(style_id) = imp
+            .folder_styles
+            .borrow()
+            .get(selected)
+            .map(|style| style.id.clone())
+        else {
+            return;
+        };
+        debug!("Selected folder style: {}", style_id);
+        let _ = imp.settings.set_string("folder-style", &style_id);
+        self.show_folder_style_preview();
+    }
+
+    fn show_folder_style_preview(&self) {
+        let imp = self.imp();
+        let styles = imp.folder_styles.borrow();
+        let Some(style) = styles.get(imp.folder_style_row.selected() as usize) else {
+            return;
+        };
+        match &style.preview {
+            Some(FolderSource::Resource(path)) => imp.folder_style_preview.set_resource(Some(path)),
+            Some(FolderSource::Path(path)) => imp.folder_style_preview.set_from_file(Some(path)),
+            None => imp.folder_style_preview.clear(),
+        };
+        imp.folder_style_row.set_subtitle(&style.description);
+    }
+
+    // Styles are installed by copying them into this directory
+    pub async fn open_folder_styles_directory(&self) {
+        let directory = FolderStyle::user_directory();
+        if let Err(e) = fs::create_dir_all(&directory) {
+            self.can_error::<()>(Err(Box::new(e)));
+            return;
+        }
+        let launcher = gtk::FileLauncher::new(Some(&gio::File::for_path(&directory)));
+        let win = self.native().and_downcast::<gtk::Window>();
+        if let Err(e) = launcher.launch_future(win.as_ref()).await {
+            error!("Could not open folder styles directory {}", e);
+        };
+    }
+}
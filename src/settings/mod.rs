@@ -1,2 +1,5 @@
+pub mod color_presets;
 pub mod custom_folder;
+pub mod folder_styles;
 pub mod settings;
+pub mod top_cache;
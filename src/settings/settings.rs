@@ -1,5 +1,10 @@
 use crate::config::{APP_ID, PROFILE};
 use crate::glib::clone;
+use crate::objects::color_preset::ColorPreset;
+use crate::objects::folder_style::FolderStyle;
+use crate::objects::iconic_error::IconicError;
+use crate::objects::recolor::HexColor;
+use crate::objects::resampling::Resampling;
 use crate::Results;
 use adw::prelude::AlertDialogExt;
 use adw::prelude::AlertDialogExtManual;
@@ -14,11 +19,18 @@ use gtk::prelude::*;
 use gtk::subclass::prelude::*;
 use gtk::*;
 use log::*;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::{env, fs, path};
 
 use crate::GtkTestWindow;
 
+// The colors in the folder color dropdown, color presets are listed after these
+pub const FOLDER_COLORS: [&str; 10] = [
+    "Blue", "Teal", "Green", "Yellow", "Orange", "Red", "Pink", "Purple", "Slate", "Custom",
+];
+
 mod imp {
     use super::*;
 
@@ -54,14 +66,29 @@ mod imp {
         #[template_child]
         pub use_builtin_icons_expander: TemplateChild<adw::ExpanderRow>,
         #[template_child]
+        pub folder_style_row: TemplateChild<adw::ComboRow>,
+        #[template_child]
+        pub folder_style_preview: TemplateChild<gtk::Image>,
+        pub folder_styles: RefCell<Vec<FolderStyle>>,
+        #[template_child]
         pub use_system_color: TemplateChild<adw::SwitchRow>,
         #[template_child]
         pub store_top_images: TemplateChild<adw::SwitchRow>,
         #[template_child]
         pub automatic_regeneration: TemplateChild<adw::SwitchRow>,
         #[template_child]
+        pub resampling_filter: TemplateChild<adw::ComboRow>,
+        #[template_child]
+        pub embed_color_profile: TemplateChild<adw::SwitchRow>,
+        #[template_child]
         pub cache_size: TemplateChild<adw::ActionRow>,
         #[template_child]
+        pub cache_quota: TemplateChild<adw::SpinRow>,
+        #[template_child]
+        pub browse_top_cache: TemplateChild<adw::ButtonRow>,
+        pub top_cache_group: RefCell<Option<adw::PreferencesGroup>>,
+        pub top_cache_rows: RefCell<Vec<adw::ExpanderRow>>,
+        #[template_child]
         pub reset_top_cache: TemplateChild<adw::ButtonRow>,
         #[template_child]
         pub primary_color_row: TemplateChild<adw::ActionRow>,
@@ -71,6 +98,24 @@ mod imp {
         pub secondary_color_row: TemplateChild<adw::ActionRow>,
         #[template_child]
         pub secondary_folder_color: TemplateChild<gtk::ColorDialogButton>,
+        #[template_child]
+        pub derive_color_row: TemplateChild<adw::ActionRow>,
+        #[template_child]
+        pub derive_folder_color: TemplateChild<gtk::ColorDialogButton>,
+        #[template_child]
+        pub derive_preview_row: TemplateChild<adw::ActionRow>,
+        #[template_child]
+        pub derive_preview: TemplateChild<gtk::Picture>,
+        pub derived_colors: RefCell<Option<(HexColor, HexColor)>>,
+        #[template_child]
+        pub color_presets_group: TemplateChild<adw::PreferencesGroup>,
+        pub color_preset_rows: RefCell<Vec<adw::EntryRow>>,
+        pub updating_color_list: Cell<bool>,
+        #[template_child]
+        pub bottom_colors_group: TemplateChild<adw::PreferencesGroup>,
+        #[template_child]
+        pub bottom_follow_accent: TemplateChild<adw::SwitchRow>,
+        pub bottom_color_rows: RefCell<Vec<adw::ActionRow>>,
         pub settings: gio::Settings,
     }
 
@@ -96,15 +141,35 @@ mod imp {
                 use_external_icon_button: TemplateChild::default(),
                 use_external_icon_expander: TemplateChild::default(),
                 use_builtin_icons_expander: TemplateChild::default(),
+                folder_style_row: TemplateChild::default(),
+                folder_style_preview: TemplateChild::default(),
+                folder_styles: RefCell::new(vec![]),
                 use_system_color: TemplateChild::default(),
                 store_top_images: TemplateChild::default(),
                 automatic_regeneration: TemplateChild::default(),
+                resampling_filter: TemplateChild::default(),
+                embed_color_profile: TemplateChild::default(),
                 cache_size: TemplateChild::default(),
+                cache_quota: TemplateChild::default(),
+                browse_top_cache: TemplateChild::default(),
+                top_cache_group: RefCell::new(None),
+                top_cache_rows: RefCell::new(vec![]),
                 reset_top_cache: TemplateChild::default(),
                 primary_color_row: TemplateChild::default(),
                 primary_folder_color: TemplateChild::default(),
                 secondary_color_row: TemplateChild::default(),
                 secondary_folder_color: TemplateChild::default(),
+                derive_color_row: TemplateChild::default(),
+                derive_folder_color: TemplateChild::default(),
+                derive_preview_row: TemplateChild::default(),
+                derive_preview: TemplateChild::default(),
+                derived_colors: RefCell::new(None),
+                color_presets_group: TemplateChild::default(),
+                color_preset_rows: RefCell::new(vec![]),
+                updating_color_list: Cell::new(false),
+                bottom_colors_group: TemplateChild::default(),
+                bottom_follow_accent: TemplateChild::default(),
+                bottom_color_rows: RefCell::new(vec![]),
                 // reveal_custom_colors: TemplateChild::default(),
             }
         }
@@ -143,6 +208,30 @@ mod imp {
                     }
                 ));
             });
+            klass.install_action("app.apply_derived_colors", None, move |win, _, _| {
+                win.apply_derived_colors();
+            });
+            klass.install_action("app.open_folder_styles", None, move |win, _, _| {
+                glib::spawn_future_local(clone!(
+                    #[weak]
+                    win,
+                    async move {
+                        win.open_folder_styles_directory().await;
+                    }
+                ));
+            });
+            klass.install_action("app.save_color_preset", None, move |win, _, _| {
+                glib::spawn_future_local(clone!(
+                    #[weak]
+                    win,
+                    async move {
+                        win.save_color_preset().await;
+                    }
+                ));
+            });
+            klass.install_action("app.reset_bottom_colors", None, move |win, _, _| {
+                win.reset_bottom_colors();
+            });
             klass.install_action("app.dnd_switch", None, move |win, _, _| {
                 win.dnd_row_expand(false);
             });
@@ -200,8 +289,8 @@ impl PreferencesDialog {
         if imp.settings.string("default-dnd-action") == "bottom" {
             imp.radio_button_bottom.set_active(true);
         }
-        imp.select_bottom_color
-            .set_selected(imp.settings.int("selected-accent-color-index") as u32);
+        win.load_folder_styles();
+        win.load_color_presets();
         win.load_set_colors();
         win.dnd_row_expand(true);
         win.set_path_title();
@@ -210,10 +299,11 @@ impl PreferencesDialog {
         win.setup_settings();
         win.get_file_size();
         win.show_color_options();
+        win.load_bottom_colors();
         win
     }
 
-    fn load_set_colors(&self) {
+    pub fn load_set_colors(&self) {
         let imp = self.imp();
         let current_primary = imp.settings.string("primary-folder-color");
         let current_secondary = imp.settings.string("secondary-folder-color");
@@ -231,6 +321,9 @@ impl PreferencesDialog {
         imp.settings
             .bind("store-top-in-cache", &*imp.store_top_images, "active")
             .build();
+        imp.settings
+            .bind("bottom-follow-accent", &*imp.bottom_follow_accent, "active")
+            .build();
         imp.settings
             .bind(
                 "automatic-regeneration",
@@ -238,6 +331,36 @@ impl PreferencesDialog {
                 "active",
             )
             .build();
+        imp.settings
+            .bind("embed-color-profile", &*imp.embed_color_profile, "active")
+            .build();
+        let resampling_names: Vec<String> = Resampling::ALL
+            .iter()
+            .map(|resampling| resampling.name())
+            .collect();
+        let resampling_names: Vec<&str> =
+            resampling_names.iter().map(|name| name.as_str()).collect();
+        imp.resampling_filter
+            .set_model(Some(&gtk::StringList::new(&resampling_names)));
+        let current_resampling = Resampling::from_id(&imp.settings.string("resampling-filter"));
+        imp.resampling_filter.set_selected(
+            Resampling::ALL
+                .iter()
+                .position(|resampling| *resampling == current_resampling)
+                .unwrap_or(0) as u32,
+        );
+        imp.resampling_filter.connect_selected_notify(clone!(
+            #[weak(rename_to = win)]
+            self,
+            move |row| {
+                if let Some(resampling) = Resampling::ALL.get(row.selected() as usize) {
+                    let _ = win
+                        .imp()
+                        .settings
+                        .set_string("resampling-filter", resampling.id());
+                }
+            }
+        ));
         imp.svg_image_size.set_value(current_value as f64);
         imp.svg_image_size.connect_changed(clone!(
             #[weak(rename_to = win)]
@@ -248,6 +371,18 @@ impl PreferencesDialog {
                 let _ = win.imp().settings.set("svg-render-size", value);
             }
         ));
+        imp.cache_quota
+            .set_value(imp.settings.int("top-cache-quota") as f64);
+        imp.cache_quota.connect_changed(clone!(
+            #[weak(rename_to = win)]
+            self,
+            move |row| {
+                let _ = win
+                    .imp()
+                    .settings
+                    .set_int("top-cache-quota", row.value() as i32);
+            }
+        ));
         let current_value: i32 = imp.settings.get("thumbnail-size");
         imp.thumbnail_image_size.set_value(current_value as f64);
         imp.thumbnail_image_size.connect_changed(clone!(
@@ -264,6 +399,14 @@ impl PreferencesDialog {
             self,
             move |_| {
                 this.bottom_image_expander(false);
+                this.load_bottom_colors();
+            }
+        ));
+        imp.folder_style_row.connect_selected_item_notify(clone!(
+            #[weak (rename_to = this)]
+            self,
+            move |_| {
+                this.folder_style_selected();
             }
         ));
         imp.select_bottom_color.connect_selected_item_notify(clone!(
@@ -290,6 +433,13 @@ impl PreferencesDialog {
                 this.show_color_options();
             }
         ));
+        imp.browse_top_cache.connect_activated(clone!(
+            #[weak (rename_to = this)]
+            self,
+            move |_| {
+                this.show_top_cache_browser();
+            }
+        ));
         imp.reset_top_cache.connect_activated(clone!(
             #[weak (rename_to = this)]
             self,
@@ -314,6 +464,13 @@ impl PreferencesDialog {
                     .set_string("primary-folder-color", &this.rgba_to_hex(color));
             }
         ));
+        imp.derive_folder_color.connect_rgba_notify(clone!(
+            #[weak (rename_to = this)]
+            self,
+            move |_| {
+                this.preview_derived_colors();
+            }
+        ));
         imp.secondary_folder_color.connect_rgba_notify(clone!(
             #[weak (rename_to = this)]
             self,
@@ -344,7 +501,7 @@ impl PreferencesDialog {
         };
     }
 
-    fn get_file_size(&self) {
+    pub fn get_file_size(&self) {
         let imp = self.imp();
         let mut path = self.get_cache_path();
         path.push("top_images");
@@ -355,15 +512,23 @@ impl PreferencesDialog {
     }
 
     fn get_selected_accent_color(&self, init: bool) {
-        let color_vec = vec![
-            "Blue", "Teal", "Green", "Yellow", "Orange", "Red", "Pink", "Purple", "Slate", "Custom",
-        ];
         let imp = self.imp();
+        if imp.updating_color_list.get() {
+            return;
+        }
         let selected_index = imp.select_bottom_color.selected() as usize;
-        let selected_color = color_vec[selected_index];
+        let selected_color = match FOLDER_COLORS.get(selected_index) {
+            Some(color) => color.to_string(),
+            None => match ColorPreset::load_all(&imp.settings)
+                .get(selected_index - FOLDER_COLORS.len())
+            {
+                Some(preset) => preset.selected_value(),
+                None => return,
+            },
+        };
         debug!("Selected accent color: {selected_color}");
         if !init {
-            let _ = imp.settings.set("selected-accent-color", selected_color);
+            let _ = imp.settings.set("selected-accent-color", &selected_color);
             let _ = imp
                 .settings
                 .set("selected-accent-color-index", selected_index as i32);
@@ -395,9 +560,8 @@ impl PreferencesDialog {
 
                 match file {
                     Ok(x) => {
-                        info!("{:#?}", &x.path().unwrap());
-                        let path: &str = &x.path().unwrap().into_os_string().into_string().unwrap();
-                        win.can_error(win.set_path(path));
+                        info!("{}", x.uri());
+                        win.can_error(win.set_path(&x));
                     }
                     Err(y) => {
                         warn!("{:#?}", y);
@@ -407,19 +571,35 @@ impl PreferencesDialog {
         ));
     }
 
-    fn set_path(&self, path: &str) -> Results<()> {
-        self.copy_folder_image_to_cache(path::PathBuf::from(path))?;
-        self.imp().settings.set("folder-svg-path", path)?;
+    // The path is stored in the settings, so files without a local path like sftp:// can not be used
+    fn set_path(&self, file: &gio::File) -> Results<()> {
+        let path = file
+            .path()
+            .and_then(|path| path.to_str().map(|path| path.to_string()))
+            .ok_or_else(|| IconicError::Io(format!("\"{}\" has no local path", file.uri())))?;
+        self.copy_folder_image_to_cache(path::PathBuf::from(&path))?;
+        // Colors picked for the previous bottom icon do not make sense for the new one
+        self.imp()
+            .settings
+            .set("bottom-color-map", HashMap::<String, String>::new())?;
+        self.imp().settings.set("folder-svg-path", path.as_str())?;
         self.set_path_title();
+        self.load_bottom_colors();
         Ok(())
     }
 
     fn copy_folder_image_to_cache(&self, original_path: path::PathBuf) -> Results<()> {
         let cache_dir = self.get_cache_path();
-        let file_name = format!(
-            "folder.{}",
-            original_path.extension().unwrap().to_str().unwrap()
-        );
+        let extension = original_path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .ok_or_else(|| {
+                IconicError::Io(format!(
+                    "\"{}\" has no file extension",
+                    original_path.display()
+                ))
+            })?;
+        let file_name = format!("folder.{}", extension);
         self.imp()
             .settings
             .set("folder-cache-name", file_name.clone())?;
@@ -428,7 +608,7 @@ impl PreferencesDialog {
         Ok(())
     }
 
-    fn can_error<T>(&self, result: Results<T>) {
+    pub fn can_error<T>(&self, result: Results<T>) {
         let _ = result.map_err(|e| {
             const RESPONSE_OK: &str = "OK";
             let dialog = adw::AlertDialog::builder()
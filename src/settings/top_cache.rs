@@ -0,0 +1,199 @@
+use adw::prelude::{
+    AlertDialogExt, AlertDialogExtManual, ExpanderRowExt, PreferencesDialogExt,
+    PreferencesGroupExt, PreferencesPageExt,
+};
+use gettextrs::{gettext, ngettext};
+use gio::subclass::prelude::ObjectSubclassIsExt;
+use gtk::{
+    gdk, glib,
+    prelude::{ButtonExt, WidgetExt},
+};
+use log::*;
+
+use crate::objects::file::File;
+use crate::objects::resampling::Resampling;
+use crate::objects::top_image_cache::{CacheEntry, TopImageCache};
+use crate::settings::settings::PreferencesDialog;
+use crate::GtkTestWindow;
+use crate::RUNTIME;
+
+const THUMBNAIL_SIZE: i32 = 48;
+
+impl PreferencesDialog {
+    // Opens a page listing every cached top image, with the icons that need it for regeneration
+    pub fn show_top_cache_browser(&self) {
+        let group = adw::PreferencesGroup::builder()
+            .title(gettext("Cached Top Images"))
+            .build();
+        let page = adw::PreferencesPage::new();
+        page.add(&group);
+        let toolbar = adw::ToolbarView::new();
+        toolbar.add_top_bar(&adw::HeaderBar::new());
+        toolbar.set_content(Some(&page));
+        let navigation_page = adw::NavigationPage::builder()
+            .title(gettext("Cache"))
+            .child(&toolbar)
+            .build();
+        self.imp().top_cache_group.replace(Some(group));
+        self.load_top_cache_entries();
+        self.push_subpage(&navigation_page);
+    }
+
+    fn load_top_cache_entries(&self) {
+        let imp = self.imp();
+        let Some(group) = imp.top_cache_group.borrow().clone() else {
+            return;
+        };
+        for row in imp.top_cache_rows.take() {
+            group.remove(&row);
+        }
+        let cache = TopImageCache::open(&self.get_cache_path());
+        let entries = cache.top_entries(&GtkTestWindow::get_data_path());
+        group.set_description(Some(&match entries.is_empty() {
+            true => gettext("No top images are cached"),
+            false => gettext("Most recently used first"),
+        }));
+        for entry in entries {
+            let row = self.create_top_cache_row(&entry);
+            group.add(&row);
+            imp.top_cache_rows.borrow_mut().push(row);
+        }
+    }
+
+    fn create_top_cache_row(&self, entry: &CacheEntry) -> adw::ExpanderRow {
+        let last_used = glib::DateTime::from_unix_local(entry.last_used)
+            .and_then(|date| date.format("%x"))
+            .map(|date| date.to_string())
+            .unwrap_or_default();
+        let dependents = match entry.dependents.len() {
+            0 => gettext("Not used by generated icons"),
+            count => ngettext("Used by {} icon", "Used by {} icons", count as u32)
+                .replace("{}", &count.to_string()),
+        };
+        let row = adw::ExpanderRow::builder()
+            .title(entry.original_name.as_deref().unwrap_or(&entry.name))
+            .title_lines(1)
+            .subtitle(format!(
+                "{} · {} · {}",
+                glib::format_size(entry.size),
+                gettext("Last used {}").replace("{}", &last_used),
+                dependents
+            ))
+            .use_markup(false)
+            .enable_expansion(!entry.dependents.is_empty())
+            .build();
+
+        let thumbnail = gtk::Image::from_icon_name("image-x-generic-symbolic");
+        thumbnail.set_pixel_size(THUMBNAIL_SIZE);
+        row.add_prefix(&thumbnail);
+        self.load_top_cache_thumbnail(entry, &thumbnail);
+
+        let delete = gtk::Button::builder()
+            .icon_name("user-trash-symbolic")
+            .tooltip_text(gettext("Remove From Cache"))
+            .valign(gtk::Align::Center)
+            .build();
+        delete.add_css_class("flat");
+        row.add_suffix(&delete);
+
+        for dependent in &entry.dependents {
+            let dependent_row = adw::ActionRow::builder()
+                .title(
+                    dependent
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .to_string(),
+                )
+                .use_markup(false)
+                .build();
+            row.add_row(&dependent_row);
+        }
+
+        let entry = entry.clone();
+        delete.connect_clicked(glib::clone!(
+            #[weak (rename_to = this)]
+            self,
+            move |_| {
+                glib::spawn_future_local(glib::clone!(
+                    #[weak]
+                    this,
+                    #[strong]
+                    entry,
+                    async move {
+                        this.remove_top_cache_entry(&entry).await;
+                    }
+                ));
+            }
+        ));
+        row
+    }
+
+    // Cached top images have no extension, so they are read like dropped files
+    fn load_top_cache_thumbnail(&self, entry: &CacheEntry, image: &gtk::Image) {
+        let path = entry.path.clone();
+        glib::spawn_future_local(glib::clone!(
+            #[weak]
+            image,
+            async move {
+                let thumbnail = RUNTIME
+                    .spawn_blocking(move || {
+                        File::from_path(path, THUMBNAIL_SIZE, THUMBNAIL_SIZE, Resampling::Lanczos3)
+                            .map(|file| file.thumbnail.into_rgba8())
+                    })
+                    .await
+                    .unwrap();
+                match thumbnail {
+                    Ok(thumbnail) => {
+                        let texture = gdk::MemoryTexture::new(
+                            thumbnail.width() as i32,
+                            thumbnail.height() as i32,
+                            gdk::MemoryFormat::R8g8b8a8,
+                            &glib::Bytes::from(thumbnail.as_raw()),
+                            thumbnail.width() as usize * 4,
+                        );
+                        image.set_paintable(Some(&texture));
+                    }
+                    Err(err) => debug!("No thumbnail for cached top image: {}", err),
+                }
+            }
+        ));
+    }
+
+    // Asks first if generated icons still need the top image, they can not be regenerated without it
+    async fn remove_top_cache_entry(&self, entry: &CacheEntry) {
+        const RESPONSE_REMOVE: &str = "remove";
+        const RESPONSE_CANCEL: &str = "cancel";
+        if !entry.dependents.is_empty() {
+            let dialog = adw::AlertDialog::builder()
+                .heading(gettext("Remove Image Still in Use?"))
+                .body(
+                    ngettext(
+                        "{} generated icon needs this image to be regenerated. After removing it, the icon keeps its current color",
+                        "{} generated icons need this image to be regenerated. After removing it, the icons keep their current color",
+                        entry.dependents.len() as u32,
+                    )
+                    .replace("{}", &entry.dependents.len().to_string()),
+                )
+                .default_response(RESPONSE_CANCEL)
+                .close_response(RESPONSE_CANCEL)
+                .build();
+            dialog.add_response(RESPONSE_CANCEL, &gettext("Cancel"));
+            dialog.add_response(RESPONSE_REMOVE, &gettext("Remove"));
+            dialog.set_response_appearance(RESPONSE_REMOVE, adw::ResponseAppearance::Destructive);
+            if &*dialog.choose_future(self).await != RESPONSE_REMOVE {
+                return;
+            }
+        }
+        let cache = TopImageCache::open(&self.get_cache_path());
+        match cache.remove(&entry.name) {
+            Ok(()) => {
+                info!("Removed cached top image {}", entry.name);
+                cache.save();
+            }
+            Err(err) => self.can_error::<()>(Err(Box::new(err))),
+        }
+        self.load_top_cache_entries();
+        self.get_file_size();
+    }
+}
@@ -19,8 +19,14 @@
  */
 
 use crate::glib::clone;
-use crate::objects::file::File;
+use crate::objects::color_preset::ColorPreset;
+use crate::objects::content_hash::ContentHash;
+use crate::objects::file::{File, DOCUMENT_MIME_TYPES};
+use crate::objects::folder_style::FolderStyle;
+use crate::objects::iconic_error::IconicError;
+use crate::objects::render_cache::RenderCache;
 use crate::settings::settings::PreferencesDialog;
+use crate::windows::perspective::PerspectiveCorners;
 use adw::prelude::AlertDialogExtManual;
 use adw::{prelude::*, subclass::prelude::*};
 use gettextrs::gettext;
@@ -36,6 +42,7 @@ use std::env;
 use std::fs;
 use std::hash::RandomState;
 use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
 use std::sync::{Arc, Mutex};
 
 use crate::config::{APP_ICON, APP_ID, PROFILE};
@@ -90,6 +97,10 @@ mod imp {
         #[template_child]
         pub main_status_page: TemplateChild<adw::StatusPage>,
         #[template_child]
+        pub top_image_gallery_clamp: TemplateChild<adw::Clamp>,
+        #[template_child]
+        pub top_image_gallery: TemplateChild<gtk::FlowBox>,
+        #[template_child]
         pub image_preferences: TemplateChild<adw::Clamp>,
         #[template_child]
         pub regeneration_progress: TemplateChild<gtk::ProgressBar>,
@@ -99,9 +110,26 @@ mod imp {
         pub popover_menu: TemplateChild<gtk::PopoverMenu>,
         #[template_child]
         pub gesture_click: TemplateChild<gtk::GestureClick>,
+        #[template_child]
+        pub rotation_row: TemplateChild<adw::ActionRow>,
+        #[template_child]
+        pub rotation_scale: TemplateChild<gtk::Scale>,
+        #[template_child]
+        pub flip_row: TemplateChild<adw::ActionRow>,
+        #[template_child]
+        pub flip_horizontal_button: TemplateChild<gtk::ToggleButton>,
+        #[template_child]
+        pub flip_vertical_button: TemplateChild<gtk::ToggleButton>,
+        #[template_child]
+        pub perspective_action_row: TemplateChild<adw::ExpanderRow>,
+        #[template_child]
+        pub perspective_switch: TemplateChild<gtk::Switch>,
+        #[template_child]
+        pub perspective_preset: TemplateChild<adw::ComboRow>,
 
         pub bottom_image_file: Arc<Mutex<Option<File>>>,
         pub default_color: RefCell<HashMap<String, gdk::RGBA, RandomState>>,
+        pub folder_style: RefCell<FolderStyle>,
         pub top_image_file: Arc<Mutex<Option<File>>>,
         pub saved_file: Arc<Mutex<Option<gio::File>>>,
         pub file_created: RefCell<bool>,
@@ -112,6 +140,15 @@ mod imp {
         pub signals: RefCell<Vec<glib::SignalHandlerId>>,
         pub settings: gio::Settings,
         pub count: RefCell<i32>,
+        pub perspective_corners: RefCell<PerspectiveCorners>,
+        // The bottom folder style the perspective preset was last loaded for
+        pub perspective_style: RefCell<Option<String>>,
+        pub perspective_preset_handler: RefCell<Option<glib::SignalHandlerId>>,
+        pub top_image_bounds: RefCell<(f32, f32, f32, f32)>,
+        pub dragged_corner: RefCell<Option<usize>>,
+        pub render_cache: Arc<RenderCache>,
+        pub render_requests: Arc<AtomicU64>,
+        pub render_running: RefCell<bool>,
     }
 
     impl Default for GtkTestWindow {
@@ -143,17 +180,36 @@ mod imp {
                 size: TemplateChild::default(),
                 stack: TemplateChild::default(),
                 main_status_page: TemplateChild::default(),
+                top_image_gallery_clamp: TemplateChild::default(),
+                top_image_gallery: TemplateChild::default(),
                 monochrome_invert: TemplateChild::default(),
                 image_loading_spinner: TemplateChild::default(),
                 regeneration_progress: TemplateChild::default(),
                 regeneration_file: TemplateChild::default(),
                 popover_menu: TemplateChild::default(),
                 gesture_click: TemplateChild::default(),
+                rotation_row: TemplateChild::default(),
+                rotation_scale: TemplateChild::default(),
+                flip_row: TemplateChild::default(),
+                flip_horizontal_button: TemplateChild::default(),
+                flip_vertical_button: TemplateChild::default(),
+                perspective_action_row: TemplateChild::default(),
+                perspective_switch: TemplateChild::default(),
+                perspective_preset: TemplateChild::default(),
                 settings: gio::Settings::new(APP_ID),
                 count: RefCell::new(0),
                 temp_image_loaded: RefCell::new(false),
                 default_color: RefCell::new(HashMap::new()),
+                folder_style: RefCell::new(FolderStyle::built_in()),
                 last_dnd_generated_name: RefCell::new(None),
+                perspective_corners: RefCell::new([(0.0, 0.0); 4]),
+                perspective_style: RefCell::new(None),
+                perspective_preset_handler: RefCell::new(None),
+                top_image_bounds: RefCell::new((0.0, 0.0, 0.0, 0.0)),
+                dragged_corner: RefCell::new(None),
+                render_cache: Arc::new(RenderCache::default()),
+                render_requests: Arc::new(AtomicU64::new(0)),
+                render_running: RefCell::new(false),
             }
         }
     }
@@ -185,6 +241,15 @@ mod imp {
                     }
                 ));
             });
+            klass.install_action("app.open_app_icon", None, move |win, _, _| {
+                glib::spawn_future_local(clone!(
+                    #[weak]
+                    win,
+                    async move {
+                        win.load_top_from_installed_app().await;
+                    }
+                ));
+            });
             klass.install_action("app.open_file_location", None, move |win, _, _| {
                 glib::spawn_future_local(clone!(
                     #[weak]
@@ -227,12 +292,9 @@ mod imp {
                         let previous_stack = imp.stack.visible_child_name().unwrap();
                         debug!("previous stack {}", previous_stack);
                         imp.stack.set_visible_child_name("regenerating_page");
-                        match win.regenerate_icons(true).await {
-                            Ok(_) => (),
-                            Err(x) => {
-                                win.show_error_popup(&format!("{}", x), true, None);
-                            }
-                        };
+                        if let Err(error) = win.regenerate_icons(true).await {
+                            win.show_iconic_error(&error);
+                        }
 
                         imp.toast_overlay.add_toast(adw::Toast::new(&gettext(
                             "Regeneration sucessful, restart nautilus",
@@ -248,12 +310,9 @@ mod imp {
                     win,
                     async move {
                         win.drag_and_drop_information_dialog();
-                        match win.open_save_file_dialog().await {
-                            Ok(_) => (),
-                            Err(error) => {
-                                win.show_error_popup(&error.to_string(), true, Some(error));
-                            }
-                        };
+                        if let Err(error) = win.open_save_file_dialog().await {
+                            win.show_iconic_error(&error);
+                        }
                     }
                 ));
             });
@@ -263,6 +322,24 @@ mod imp {
             klass.install_action("app.reset_color", None, move |win, _, _| {
                 win.reset_colors();
             });
+            klass.install_action("app.rotate_left", None, move |win, _, _| {
+                win.rotate_step(-90.0);
+            });
+            klass.install_action("app.rotate_right", None, move |win, _, _| {
+                win.rotate_step(90.0);
+            });
+            klass.install_action("app.reset_perspective", None, move |win, _, _| {
+                win.reset_perspective();
+            });
+            klass.install_action("app.match_top_colors", None, move |win, _, _| {
+                glib::spawn_future_local(clone!(
+                    #[weak]
+                    win,
+                    async move {
+                        win.match_folder_to_top_image().await;
+                    }
+                ));
+            });
         }
 
         fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
@@ -405,6 +482,7 @@ impl GtkTestWindow {
         ]));
         win.setup_defaults();
         win.create_popover_image();
+        win.setup_perspective_handles();
         win
     }
 
@@ -424,12 +502,24 @@ impl GtkTestWindow {
 
     pub fn default_sliders(&self) {
         let imp = self.imp();
-        imp.x_scale.add_mark(0.0, gtk::PositionType::Top, None);
+        // The top image starts in the middle of the safe area of the folder style
+        let (x, y, size) = imp.folder_style.borrow().safe_area.slider_values();
+        imp.x_scale.clear_marks();
+        imp.y_scale.clear_marks();
+        imp.size.clear_marks();
+        imp.x_scale.set_value(x);
+        imp.x_scale.add_mark(x, gtk::PositionType::Top, None);
         imp.y_scale.add_mark(0.0, gtk::PositionType::Bottom, None);
-        imp.y_scale.set_value(9.447);
-        imp.size.set_value(24.0);
-        imp.size.add_mark(24.0, gtk::PositionType::Top, None);
-        imp.y_scale.add_mark(9.447, gtk::PositionType::Bottom, None);
+        imp.y_scale.set_value(y);
+        imp.size.set_value(size);
+        imp.size.add_mark(size, gtk::PositionType::Top, None);
+        imp.y_scale.add_mark(y, gtk::PositionType::Bottom, None);
+        imp.rotation_scale.set_value(0.0);
+        imp.rotation_scale
+            .add_mark(0.0, gtk::PositionType::Top, None);
+        imp.flip_horizontal_button.set_active(false);
+        imp.flip_vertical_button.set_active(false);
+        self.reset_perspective();
     }
 
     pub fn setup_defaults(&self) {
@@ -446,6 +536,7 @@ impl GtkTestWindow {
         self.setup_settings();
         self.setup_update();
         self.load_folder_path_from_settings();
+        self.load_top_image_gallery();
     }
 
     pub fn to_rgba(r: u8, g: u8, b: u8) -> gdk::RGBA {
@@ -458,7 +549,9 @@ impl GtkTestWindow {
         let imp = self.imp();
         //imp.main_status_page.remove_controller(&imp.drop_target_item.borrow().clone().unwrap());
         let generated_image = imp.generated_image.borrow().clone().unwrap();
-        let file_hash = imp.top_image_file.lock().unwrap().clone().unwrap().hash;
+        // Before the file is named, the names of the cached images end up in it
+        self.cache_generation_images();
+        let file_hash = imp.top_image_file.lock().unwrap().as_ref().unwrap().hash;
         let icon = self.dynamic_image_to_texture(&generated_image.resize(
             64,
             64,
@@ -473,7 +566,9 @@ impl GtkTestWindow {
             #[weak (rename_to = win)]
             self,
             async move {
-                win.save_file(gio_file_clone).await.unwrap();
+                if let Err(error) = win.save_file(gio_file_clone).await {
+                    win.show_iconic_error(&error);
+                }
             }
         ));
         Some(gdk::ContentProvider::for_value(&glib::Value::from(
@@ -481,9 +576,9 @@ impl GtkTestWindow {
         )))
     }
 
-    pub fn create_drag_file(&self, file_hash: u64) -> gio::File {
+    pub fn create_drag_file(&self, file_hash: ContentHash) -> gio::File {
         // let imp = self.imp();
-        let data_path = self.get_data_path();
+        let data_path = Self::get_data_path();
         debug!("data path: {:?}", data_path);
         // let random_number = random::<u64>();
         let properties_string = self.create_image_properties_string();
@@ -500,6 +595,13 @@ impl GtkTestWindow {
     This makes it possible to completely recreate the image if the top image is still available
     */
     fn create_image_properties_string(&self) -> String {
+        let combined_string = self.image_properties().join("-");
+        debug!("{}", &combined_string);
+        combined_string
+    }
+
+    // The properties of the file name one by one, the gallery stores them like this so minus signs are kept
+    pub fn image_properties(&self) -> Vec<String> {
         let imp = self.imp();
         let is_default = (!imp.settings.boolean("manual-bottom-image-selection")
             && imp.settings.string("selected-accent-color").as_str() == "None"
@@ -514,23 +616,67 @@ impl GtkTestWindow {
         let monochrome_blue_val = imp.monochrome_color.rgba().blue().to_string();
         let monochrome_inverted = imp.monochrome_invert.is_active() as u8;
         let is_default_monochrome = imp.monochrome_color.rgba() == self.get_default_color();
+        // Stored between 0 and 360, as a minus sign would be confused with the separator
+        let rotation_val = imp.rotation_scale.value().rem_euclid(360.0);
+        let is_flipped_horizontal = imp.flip_horizontal_button.is_active() as u8;
+        let is_flipped_vertical = imp.flip_vertical_button.is_active() as u8;
+        // Icons made with a color preset can be regenerated when the preset changes
+        let color_preset = match ColorPreset::selected(&imp.settings) {
+            Some(preset)
+                if !imp.settings.boolean("manual-bottom-image-selection")
+                    && !*imp.temp_image_loaded.borrow() =>
+            {
+                preset.id
+            }
+            _ => String::from("none"),
+        };
+        // Icons made with any other bottom image name it, it is kept in the top cache to regenerate them with
+        let custom_bottom = match self.has_custom_bottom() {
+            true => imp
+                .bottom_image_file
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map(|file| file.hash.to_string()),
+            false => None,
+        };
+        // Shifted by one to keep minus signs out like the rotation, the offsets never go below minus one
+        let perspective_val = self
+            .active_perspective_corners()
+            .iter()
+            .flat_map(|(x, y)| [(x + 1.0).to_string(), (y + 1.0).to_string()])
+            .collect::<Vec<String>>();
         debug!("is default? {}", is_default_monochrome);
-        let combined_string = format!(
-            "{}-{}-{}-{}-{}-{}-{}-{}-{}-{}-{}",
-            is_default,
-            x_scale_val,
-            y_scale_val,
-            zoom_val,
-            is_monochrome,
-            monochrome_slider,
+        let mut properties = vec![
+            is_default.to_string(),
+            x_scale_val.to_string(),
+            y_scale_val.to_string(),
+            zoom_val.to_string(),
+            is_monochrome.to_string(),
+            monochrome_slider.to_string(),
             monochrome_red_val,
             monochrome_green_val,
             monochrome_blue_val,
-            monochrome_inverted,
-            is_default_monochrome
-        );
-        debug!("{}", &combined_string);
-        combined_string
+            monochrome_inverted.to_string(),
+            is_default_monochrome.to_string(),
+            rotation_val.to_string(),
+            is_flipped_horizontal.to_string(),
+            is_flipped_vertical.to_string(),
+        ];
+        properties.extend(perspective_val);
+        properties.push(color_preset);
+        properties.push(custom_bottom.unwrap_or_else(|| String::from("none")));
+        properties
+    }
+
+    // Whether the bottom image is neither the folder in the accent color nor one of a color preset
+    pub fn has_custom_bottom(&self) -> bool {
+        let imp = self.imp();
+        let manual = imp.settings.boolean("manual-bottom-image-selection")
+            || *imp.temp_image_loaded.borrow();
+        manual
+            || (imp.settings.string("selected-accent-color").as_str() != "None"
+                && ColorPreset::selected(&imp.settings).is_none())
     }
 
     fn drag_connect_cancel(&self, reason: gdk::DragCancelReason) -> bool {
@@ -621,12 +767,30 @@ impl GtkTestWindow {
             .connect_changed(Some("secondary-folder-color"), update_folder.clone());
         imp.settings
             .connect_changed(Some("selected-accent-color"), update_folder.clone());
+        imp.settings
+            .connect_changed(Some("folder-style"), update_folder.clone());
+        imp.settings
+            .connect_changed(Some("custom-color-presets"), update_folder.clone());
         imp.settings
             .connect_changed(Some("manual-bottom-image-selection"), update_folder.clone());
+        imp.settings
+            .connect_changed(Some("bottom-color-map"), update_folder.clone());
+        imp.settings
+            .connect_changed(Some("bottom-follow-accent"), update_folder.clone());
         imp.settings
             .connect_changed(Some("svg-render-size"), resize_folder.clone());
         imp.settings
             .connect_changed(Some("thumbnail-size"), reload_thumbnails.clone());
+        imp.settings
+            .connect_changed(Some("resampling-filter"), reload_thumbnails.clone());
+        imp.settings.connect_changed(
+            Some("store-top-in-cache"),
+            clone!(
+                #[weak(rename_to = win)]
+                self,
+                move |_, _| win.load_top_image_gallery()
+            ),
+        );
     }
 
     pub fn setup_update(&self) {
@@ -729,6 +893,82 @@ impl GtkTestWindow {
                 ));
             }
         ));
+        self.imp().rotation_scale.connect_value_changed(clone!(
+            #[weak(rename_to = win)]
+            self,
+            move |_| {
+                glib::spawn_future_local(clone!(
+                    #[weak]
+                    win,
+                    async move {
+                        win.render_to_screen().await;
+                        win.imp().image_saved.replace(false);
+                        win.imp().save_button.set_sensitive(true);
+                    }
+                ));
+            }
+        ));
+        self.imp().flip_horizontal_button.connect_toggled(clone!(
+            #[weak(rename_to = win)]
+            self,
+            move |_| {
+                glib::spawn_future_local(clone!(
+                    #[weak]
+                    win,
+                    async move {
+                        win.render_to_screen().await;
+                        win.imp().image_saved.replace(false);
+                        win.imp().save_button.set_sensitive(true);
+                    }
+                ));
+            }
+        ));
+        self.imp().perspective_switch.connect_active_notify(clone!(
+            #[weak(rename_to = win)]
+            self,
+            move |_| {
+                win.enable_perspective_expand();
+                glib::spawn_future_local(clone!(
+                    #[weak]
+                    win,
+                    async move {
+                        if win.imp().stack.visible_child_name() == Some("stack_main_page".into()) {
+                            win.render_to_screen().await;
+                            win.imp().image_saved.replace(false);
+                            win.imp().save_button.set_sensitive(true);
+                        }
+                    }
+                ));
+            }
+        ));
+        let perspective_preset_handler =
+            self.imp()
+                .perspective_preset
+                .connect_selected_notify(clone!(
+                    #[weak(rename_to = win)]
+                    self,
+                    move |_| {
+                        win.perspective_preset_selected();
+                    }
+                ));
+        self.imp()
+            .perspective_preset_handler
+            .replace(Some(perspective_preset_handler));
+        self.imp().flip_vertical_button.connect_toggled(clone!(
+            #[weak(rename_to = win)]
+            self,
+            move |_| {
+                glib::spawn_future_local(clone!(
+                    #[weak]
+                    win,
+                    async move {
+                        win.render_to_screen().await;
+                        win.imp().image_saved.replace(false);
+                        win.imp().save_button.set_sensitive(true);
+                    }
+                ));
+            }
+        ));
     }
 
     fn check_regeneration_needed(&self) -> bool {
@@ -762,6 +1002,13 @@ impl GtkTestWindow {
         imp.reset_color.set_visible(false);
     }
 
+    // Rotates the top image by a fixed step, wrapping around so the slider stays between -180 and 180 degrees
+    pub fn rotate_step(&self, step: f64) {
+        let imp = self.imp();
+        let new_rotation = (imp.rotation_scale.value() + step + 180.0).rem_euclid(360.0) - 180.0;
+        imp.rotation_scale.set_value(new_rotation);
+    }
+
     // TODO: This approach is dumb. I am purposely failing a dictionary lookup and using unwrap_or to get my way
     pub fn get_default_color(&self) -> gdk::RGBA {
         let imp = self.imp();
@@ -773,11 +1020,9 @@ impl GtkTestWindow {
             accent_color = self.get_accent_color_and_dialog();
         } else if imp.settings.boolean("manual-bottom-image-selection") {
             accent_color = "Blue".to_string();
-        } else if selected_accent_color == "Custom" {
+        } else if let Some((_, secondary_color)) = self.custom_folder_colors() {
             accent_color = "Custom".to_string();
-            custom_rgb = PreferencesDialog::hex_to_rgba(
-                imp.settings.string("secondary-folder-color").into(),
-            );
+            custom_rgb = PreferencesDialog::hex_to_rgba(secondary_color);
         } else {
             accent_color = selected_accent_color.into();
         }
@@ -791,23 +1036,23 @@ impl GtkTestWindow {
         color
     }
 
-    pub async fn check_chache_icon(&self, file_name: &str) -> PathBuf {
+    pub async fn check_chache_icon(&self, file_name: &str) -> Result<PathBuf, IconicError> {
         let imp = self.imp();
         let icon_path = PathBuf::from(&imp.settings.string("folder-svg-path"));
         let cache_path = self.get_cache_path();
         let folder_icon_cache_path = cache_path.join(file_name);
         if folder_icon_cache_path.exists() {
             info!("File found in cache at: {:?}", folder_icon_cache_path);
-            return folder_icon_cache_path;
+            return Ok(folder_icon_cache_path);
         } else if icon_path.exists() {
             info!(
                 "File not found in cache, copying to: {:?}",
                 folder_icon_cache_path
             );
-            return self
+            return Ok(self
                 .copy_folder_image_to_cache(&icon_path, &cache_path)
-                .await
-                .0;
+                .await?
+                .0);
         }
         info!("File not found AT ALL");
         let dialog = self
@@ -820,10 +1065,16 @@ impl GtkTestWindow {
         match &*dialog.clone().choose_future(self).await {
             "OK" => {
                 let new_path = match self.open_file_chooser().await {
-                    Some(x) => x.path().unwrap().into_os_string().into_string().unwrap(),
+                    // The path is stored in the settings, so files without a local one can not be used
+                    Some(x) => x
+                        .path()
+                        .and_then(|path| path.to_str().map(|path| path.to_string()))
+                        .ok_or_else(|| {
+                            IconicError::Io(format!("\"{}\" has no local path", x.uri()))
+                        })?,
                     None => {
                         //adw::subclass::prelude::ActionGroupImpl::activate_action(&self, "app.quit", None);
-                        String::from("")
+                        return Err(IconicError::Io(String::from("No folder icon was selected")));
                     }
                 };
                 imp.settings
@@ -831,14 +1082,14 @@ impl GtkTestWindow {
                     .unwrap();
                 let cached_file_name = self
                     .copy_folder_image_to_cache(&PathBuf::from(new_path), &cache_path)
-                    .await
+                    .await?
                     .1;
                 imp.settings
                     .set_string("folder-cache-name", &cached_file_name)
                     .unwrap();
                 let cache_file_name = &imp.settings.string("folder-cache-name");
                 let folder_icon_cache_path = cache_path.join(cache_file_name);
-                return PathBuf::from(folder_icon_cache_path);
+                return Ok(PathBuf::from(folder_icon_cache_path));
             }
             _ => unreachable!(),
         };
@@ -848,19 +1099,26 @@ impl GtkTestWindow {
         &self,
         original_path: &PathBuf,
         cache_dir: &PathBuf,
-    ) -> (PathBuf, String) {
-        let file_name = format!(
-            "folder.{}",
-            original_path.extension().unwrap().to_str().unwrap()
-        );
+    ) -> Result<(PathBuf, String), IconicError> {
+        // The extension is kept so the type of the image can still be told from the name
+        let extension = original_path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .ok_or_else(|| {
+                IconicError::Io(format!(
+                    "\"{}\" has no file extension",
+                    original_path.display()
+                ))
+            })?;
+        let file_name = format!("folder.{}", extension);
         self.imp()
             .settings
             .set("folder-cache-name", file_name.clone())
             .unwrap();
         let cache_path = cache_dir.join(file_name.clone());
-        let _ = std::fs::copy(original_path, cache_path.clone());
+        std::fs::copy(original_path, cache_path.clone())?;
         //let test = RUNTIME.spawn_blocking(move || true).await;
-        (cache_path, file_name)
+        Ok((cache_path, file_name))
     }
 
     pub fn get_cache_path(&self) -> PathBuf {
@@ -880,7 +1138,8 @@ impl GtkTestWindow {
         cache_path
     }
 
-    pub fn get_data_path(&self) -> PathBuf {
+    // Where generated icons are stored, also used by the preferences so both look in the same place
+    pub fn get_data_path() -> PathBuf {
         let data_path = match env::var("XDG_DATA_HOME") {
             Ok(value) => PathBuf::from(value),
             Err(_) => {
@@ -888,7 +1147,7 @@ impl GtkTestWindow {
                     .join(".data")
                     .join("nl.emphisia.icon");
                 if !config_dir.exists() {
-                    fs::create_dir(&config_dir).unwrap();
+                    fs::create_dir_all(&config_dir).unwrap();
                 }
                 config_dir
             }
@@ -924,7 +1183,12 @@ impl GtkTestWindow {
             debug!("Loaded temporary image for render");
             // Create image of nothing
             let empty_image = DynamicImage::new(1, 1, ColorType::Rgba8);
-            (*top_image).replace(File::from_image(empty_image, 1, &folder_bottom_name));
+            (*top_image).replace(File::from_image(
+                empty_image,
+                1,
+                &folder_bottom_name,
+                self.resampling(),
+            ));
             self.enable_disable_top_control(false);
 
             if imp.stack.visible_child_name() != Some("stack_main_page".into()) {
@@ -942,12 +1206,18 @@ impl GtkTestWindow {
         imp.monochrome_color.set_sensitive(enable);
         imp.monochrome_invert.set_sensitive(enable);
         imp.monochrome_switch.set_sensitive(enable);
+        imp.rotation_row.set_sensitive(enable);
+        imp.flip_row.set_sensitive(enable);
+        imp.perspective_action_row.set_sensitive(enable);
     }
 
     pub async fn open_file_chooser(&self) -> Option<gio::File> {
         let filters = gio::ListStore::new::<gtk::FileFilter>();
         let filter = gtk::FileFilter::new();
         filter.add_mime_type("image/*");
+        for mime_type in DOCUMENT_MIME_TYPES {
+            filter.add_mime_type(mime_type);
+        }
         filters.append(&filter);
         let dialog = gtk::FileDialog::builder()
             .title(gettext("Open Document"))
@@ -957,7 +1227,7 @@ impl GtkTestWindow {
         let file = dialog.open_future(Some(self)).await;
         match file {
             Ok(file) => {
-                debug!("{:?}", &file.path().unwrap());
+                debug!("{}", file.uri());
                 Some(file)
             }
             Err(error) => {
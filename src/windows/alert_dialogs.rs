@@ -1,14 +1,18 @@
 use std::error::Error;
 
-use adw::prelude::{AdwDialogExt, AlertDialogExt, AlertDialogExtManual};
+use adw::prelude::{ActionRowExt, AdwDialogExt, AlertDialogExt, AlertDialogExtManual};
 use gettextrs::gettext;
 use gio::{
     glib,
-    prelude::{SettingsExt, SettingsExtManual},
+    prelude::{AppInfoExt, SettingsExt, SettingsExtManual},
     subclass::prelude::ObjectSubclassIsExt,
 };
+use gtk::prelude::{BoxExt, ButtonExt, EditableExt, ListBoxRowExt, ToggleButtonExt, WidgetExt};
 use log::*;
 
+use crate::objects::iconic_error::IconicError;
+use crate::objects::recolor::HexColor;
+use crate::settings::settings::PreferencesDialog;
 use crate::GtkTestWindow;
 
 impl GtkTestWindow {
@@ -61,6 +65,145 @@ impl GtkTestWindow {
         }
     }
 
+    // Shows a folder in every color pair, returns the one the user picked
+    pub async fn folder_color_popup(
+        &self,
+        candidates: Vec<(HexColor, HexColor)>,
+    ) -> Option<(HexColor, HexColor)> {
+        const RESPONSE_CANCEL: &str = "cancel";
+        const RESPONSE_APPLY: &str = "apply";
+        const SWATCH_SIZE: u32 = 48;
+        let folder_style = self.imp().folder_style.borrow().clone();
+        let swatches = gtk::Box::builder()
+            .spacing(6)
+            .halign(gtk::Align::Center)
+            .build();
+        let mut buttons: Vec<gtk::ToggleButton> = vec![];
+        for (primary, secondary) in &candidates {
+            let button = gtk::ToggleButton::builder()
+                .tooltip_text(format!("#{}", secondary.to_uppercase()))
+                .build();
+            button.add_css_class("flat");
+            match folder_style
+                .recolor_template(primary, secondary)
+                .ok()
+                .and_then(|svg| PreferencesDialog::render_preview(&svg, SWATCH_SIZE).ok())
+            {
+                Some(texture) => {
+                    let image = gtk::Image::from_paintable(Some(&texture));
+                    image.set_pixel_size(SWATCH_SIZE as i32);
+                    button.set_child(Some(&image));
+                }
+                None => button.set_label(&format!("#{}", secondary.to_uppercase())),
+            };
+            match buttons.first() {
+                Some(first_button) => button.set_group(Some(first_button)),
+                None => button.set_active(true),
+            };
+            swatches.append(&button);
+            buttons.push(button);
+        }
+        let dialog = adw::AlertDialog::builder()
+            .heading(gettext("Folder Color"))
+            .body(gettext("Pick one of the colors found in the top image"))
+            .extra_child(&swatches)
+            .close_response(RESPONSE_CANCEL)
+            .default_response(RESPONSE_APPLY)
+            .build();
+        dialog.add_response(RESPONSE_CANCEL, &gettext("Cancel"));
+        dialog.add_response(RESPONSE_APPLY, &gettext("Apply"));
+        dialog.set_response_appearance(RESPONSE_APPLY, adw::ResponseAppearance::Suggested);
+
+        match &*dialog.clone().choose_future(self).await {
+            RESPONSE_APPLY => buttons
+                .iter()
+                .position(|button| button.is_active())
+                .and_then(|index| candidates.get(index).cloned()),
+            _ => None,
+        }
+    }
+
+    // Lists the installed apps with a search entry, returns the app the user picked
+    pub async fn app_picker_popup(&self, apps: Vec<gio::AppInfo>) -> Option<gio::AppInfo> {
+        const RESPONSE_CANCEL: &str = "cancel";
+        const RESPONSE_SELECT: &str = "select";
+        const ICON_SIZE: i32 = 32;
+        let search_entry = gtk::SearchEntry::builder()
+            .placeholder_text(gettext("Search Apps"))
+            .build();
+        let list = gtk::ListBox::builder()
+            .selection_mode(gtk::SelectionMode::Single)
+            .build();
+        list.add_css_class("boxed-list");
+        for app in &apps {
+            let row = adw::ActionRow::builder()
+                .title(glib::markup_escape_text(&app.display_name()))
+                .activatable(true)
+                .build();
+            let image = match app.icon() {
+                Some(icon) => gtk::Image::from_gicon(&icon),
+                None => gtk::Image::from_icon_name("application-x-executable"),
+            };
+            image.set_pixel_size(ICON_SIZE);
+            row.add_prefix(&image);
+            list.append(&row);
+        }
+        let scrolled_window = gtk::ScrolledWindow::builder()
+            .hscrollbar_policy(gtk::PolicyType::Never)
+            .min_content_height(360)
+            .child(&list)
+            .build();
+        let content = gtk::Box::builder()
+            .orientation(gtk::Orientation::Vertical)
+            .spacing(12)
+            .build();
+        content.append(&search_entry);
+        content.append(&scrolled_window);
+
+        let dialog = adw::AlertDialog::builder()
+            .heading(gettext("Use Icon of App"))
+            .body(gettext("The icon of the app is used as top image"))
+            .extra_child(&content)
+            .close_response(RESPONSE_CANCEL)
+            .default_response(RESPONSE_SELECT)
+            .build();
+        dialog.add_response(RESPONSE_CANCEL, &gettext("Cancel"));
+        dialog.add_response(RESPONSE_SELECT, &gettext("Select"));
+        dialog.set_response_appearance(RESPONSE_SELECT, adw::ResponseAppearance::Suggested);
+        dialog.set_response_enabled(RESPONSE_SELECT, false);
+
+        list.set_filter_func(glib::clone!(
+            #[weak]
+            search_entry,
+            #[strong]
+            apps,
+            #[upgrade_or]
+            true,
+            move |row| {
+                let search = search_entry.text().to_lowercase();
+                apps.get(row.index() as usize)
+                    .is_some_and(|app| app.display_name().to_lowercase().contains(&search))
+            }
+        ));
+        search_entry.connect_search_changed(glib::clone!(
+            #[weak]
+            list,
+            move |_| list.invalidate_filter()
+        ));
+        list.connect_row_selected(glib::clone!(
+            #[weak]
+            dialog,
+            move |_, row| dialog.set_response_enabled(RESPONSE_SELECT, row.is_some())
+        ));
+
+        match &*dialog.clone().choose_future(self).await {
+            RESPONSE_SELECT => list
+                .selected_row()
+                .and_then(|row| apps.get(row.index() as usize).cloned()),
+            _ => None,
+        }
+    }
+
     pub async fn confirm_save_changes(&self) -> Result<glib::Propagation, ()> {
         const RESPONSE_CANCEL: &str = "cancel";
         const RESPONSE_DISCARD: &str = "discard";
@@ -89,7 +232,7 @@ impl GtkTestWindow {
                     false => Ok(glib::Propagation::Stop),
                 },
                 Err(error) => {
-                    self.show_error_popup(&error.to_string(), true, Some(error));
+                    self.show_iconic_error(&error);
                     Ok(glib::Propagation::Stop)
                 }
             },
@@ -131,6 +274,15 @@ impl GtkTestWindow {
         }
     }
 
+    // Shows what went wrong together with what can be done about it
+    pub fn show_iconic_error(&self, error: &IconicError) {
+        self.show_error_popup(
+            &format!("{}\n\n{}", error.message(), error.suggestion()),
+            true,
+            Some(Box::new(error.clone())),
+        );
+    }
+
     pub fn show_error_popup(
         &self,
         message: &str,
@@ -1,12 +1,19 @@
-use crate::objects::file::File;
+use crate::objects::app_icon::AppIcon;
+use crate::objects::color_preset::ColorPreset;
+use crate::objects::color_profile;
+use crate::objects::file::{File, SourceFormat, DOCUMENT_MIME_TYPES};
+use crate::objects::folder_style::{FolderSource, FolderStyle};
+use crate::objects::iconic_error::IconicError;
+use crate::objects::palette::Palette;
+use crate::objects::recolor::{HexColor, RecolorableSvg};
+use crate::settings::settings::FOLDER_COLORS;
+use crate::windows::generation::TopLayer;
 use adw::{prelude::*, subclass::prelude::*};
 use gettextrs::gettext;
 use gio::*;
 use gtk::glib;
-use image::*;
 use log::*;
-use std::env;
-use std::error::Error;
+use std::collections::HashMap;
 use std::path::PathBuf;
 
 use crate::{GtkTestWindow, RUNTIME};
@@ -18,118 +25,290 @@ impl GtkTestWindow {
             self,
             async move {
                 let imp = win.imp();
-                let path;
+                let source;
                 imp.temp_image_loaded.replace(false);
+                let folder_style = FolderStyle::find(&imp.settings.string("folder-style"));
+                let style_changed = imp.folder_style.borrow().id != folder_style.id;
+                imp.folder_style.replace(folder_style);
                 if imp.settings.boolean("manual-bottom-image-selection") {
                     let cache_file_name: &str = &win.imp().settings.string("folder-cache-name");
-                    path = win.check_chache_icon(cache_file_name).await;
-                } else if imp.settings.string("selected-accent-color") == "Custom" {
-                    path = win.create_custom_folder_color().await;
+                    source = match win.check_chache_icon(cache_file_name).await {
+                        Ok(path) => Some(FolderSource::Path(win.recolor_custom_bottom(path).await)),
+                        Err(error) => {
+                            win.show_iconic_error(&error);
+                            None
+                        }
+                    };
+                } else if let Some((primary, secondary)) = win.custom_folder_colors() {
+                    source = win.create_custom_folder_color(primary, secondary).await;
                 } else {
-                    path = win.load_built_in_bottom_icon().await;
+                    source = win.load_built_in_bottom_icon().await;
                 }
+                // The error has been shown already, there is no folder left to fall back on
+                let Some(source) = source else {
+                    return;
+                };
                 if !imp.reset_color.is_visible() {
                     win.reset_colors();
                 }
-                info!("Loading path: {:?}", &path);
-                win.load_folder_icon(&path.into_os_string().into_string().unwrap())
-                    .await;
+                info!("Loading folder: {:?}", &source);
+                win.load_folder_source(&source).await;
+                // A position on one folder style does not make sense on another
+                if style_changed {
+                    win.default_sliders();
+                }
+                win.load_perspective_preset();
             }
         ));
     }
 
-    async fn create_custom_folder_color(&self) -> PathBuf {
+    // The primary and secondary color of the custom folder, if the custom colors or a color preset are selected
+    pub fn custom_folder_colors(&self) -> Option<(HexColor, HexColor)> {
         let imp = self.imp();
+        if let Some(preset) = ColorPreset::selected(&imp.settings) {
+            return Some((preset.primary, preset.secondary));
+        }
+        match imp.settings.string("selected-accent-color").as_str() {
+            "Custom" => Some((
+                imp.settings.string("primary-folder-color").to_lowercase(),
+                imp.settings.string("secondary-folder-color").to_lowercase(),
+            )),
+            _ => None,
+        }
+    }
+
+    async fn create_custom_folder_color(
+        &self,
+        primary: HexColor,
+        secondary: HexColor,
+    ) -> Option<FolderSource> {
         info!("Creating custom folder colors");
-        let custom_primary_color = imp.settings.string("primary-folder-color");
-        let custom_secondary_color = imp.settings.string("secondary-folder-color");
-        let folder_svg_file =
-            std::fs::read_to_string("/app/share/folder_icon/folders/folder_Custom.svg").unwrap();
-        let folder_svg_lines = folder_svg_file.lines();
-        let new_custom_folder: String = folder_svg_lines
-            .map(|row| {
-                let row_clone = row.to_string();
-                let row_clone = row_clone.replace("a4caee", &custom_primary_color);
-                let mut row_clone = row_clone.replace("438de6", &custom_secondary_color);
-                row_clone.push_str("\n");
-                row_clone
+        match self
+            .write_custom_folder(primary, secondary, "custom_folder.svg")
+            .await
+        {
+            Ok(path) => Some(FolderSource::Path(path)),
+            Err(e) => {
+                self.show_iconic_error(&e);
+                self.load_built_in_bottom_icon().await
+            }
+        }
+    }
+
+    pub async fn write_custom_folder(
+        &self,
+        primary: HexColor,
+        secondary: HexColor,
+        file_name: &str,
+    ) -> Result<PathBuf, IconicError> {
+        let template = self.imp().folder_style.borrow().template_or_built_in()?;
+        let color_mapping: HashMap<HexColor, HexColor> = HashMap::from([
+            (template.primary, primary.to_lowercase()),
+            (template.secondary, secondary.to_lowercase()),
+        ]);
+        let folder_svg_file = template.source.load()?;
+        self.write_recolored_svg(folder_svg_file, file_name, move |_| color_mapping)
+            .await
+    }
+
+    // Finds the main colors of the top image and lets the user pick one of them as custom folder color
+    pub async fn match_folder_to_top_image(&self) {
+        const FOLDER_COLOR_CANDIDATES: usize = 5;
+        let imp = self.imp();
+        let top_image = match imp.top_image_file.lock().unwrap().as_ref() {
+            Some(file) => file.dynamic_image.clone(),
+            None => return,
+        };
+        let candidates = RUNTIME
+            .spawn_blocking(move || {
+                Palette::from_image(&top_image, FOLDER_COLOR_CANDIDATES).folder_colors()
             })
-            .collect();
-        let new_custom_folder_bytes = new_custom_folder.as_bytes().to_owned();
-        let mut cache_location = self.get_cache_path();
-        cache_location.push("custom_folder.svg");
+            .await
+            .unwrap_or_default();
+        if candidates.is_empty() {
+            imp.toast_overlay.add_toast(adw::Toast::new(&gettext(
+                "No colors found in the top image",
+            )));
+            return;
+        }
+        let Some((primary, secondary)) = self.folder_color_popup(candidates).await else {
+            return;
+        };
+        // Changing these settings reloads the folder, which creates it with the new colors
+        let _ = imp
+            .settings
+            .set("primary-folder-color", primary.to_uppercase());
+        let _ = imp
+            .settings
+            .set("secondary-folder-color", secondary.to_uppercase());
+        let _ = imp.settings.set("manual-bottom-image-selection", false);
+        let _ = imp.settings.set("selected-accent-color", "Custom");
+        // Custom is the last of the folder colors
+        let _ = imp.settings.set_int(
+            "selected-accent-color-index",
+            FOLDER_COLORS.len() as i32 - 1,
+        );
+    }
+
+    // Recolors a custom bottom SVG using the colors set in the preferences, other images are left alone
+    async fn recolor_custom_bottom(&self, path: PathBuf) -> PathBuf {
+        let imp = self.imp();
+        let mut color_mapping: HashMap<HexColor, HexColor> = imp.settings.get("bottom-color-map");
+        let follow_accent = imp.settings.boolean("bottom-follow-accent");
+        if color_mapping.is_empty() && !follow_accent {
+            return path;
+        }
+        let accent_color = imp
+            .default_color
+            .borrow()
+            .get(&self.get_accent_color_and_dialog())
+            .cloned()
+            .unwrap_or_else(|| self.get_default_color());
+        let accent_hex = RecolorableSvg::to_hex(
+            (accent_color.red() * 255.0) as u8,
+            (accent_color.green() * 255.0) as u8,
+            (accent_color.blue() * 255.0) as u8,
+        );
+        let svg_data = match std::fs::read(&path) {
+            Ok(x) => x,
+            Err(e) => {
+                self.show_iconic_error(&e.into());
+                return path;
+            }
+        };
+        if !SourceFormat::detect(&svg_data).is_some_and(|format| format.is_svg()) {
+            return path;
+        }
+        match self
+            .write_recolored_svg(svg_data, "custom_folder_recolored.svg", move |colors| {
+                if follow_accent {
+                    for color in colors {
+                        color_mapping
+                            .entry(color.clone())
+                            .or_insert_with(|| RecolorableSvg::shift_hue(color, &accent_hex));
+                    }
+                }
+                color_mapping
+            })
+            .await
+        {
+            Ok(recolored_path) => recolored_path,
+            Err(e) => {
+                self.show_iconic_error(&e);
+                path
+            }
+        }
+    }
+
+    /* Parses the SVG, asks the mapping function what every color found in it should become
+    and stores the recolored SVG in the cache with the given name */
+    async fn write_recolored_svg<F>(
+        &self,
+        svg_data: Vec<u8>,
+        file_name: &str,
+        create_mapping: F,
+    ) -> Result<PathBuf, IconicError>
+    where
+        F: FnOnce(&[HexColor]) -> HashMap<HexColor, HexColor> + Send + 'static,
+    {
+        let cache_location = self.get_cache_path().join(file_name);
         let cache_location_clone = cache_location.clone();
         RUNTIME
             .spawn_blocking(move || {
-                let _ = std::fs::write(&cache_location_clone, new_custom_folder_bytes);
+                let svg = RecolorableSvg::from_data(&svg_data)?;
+                let color_mapping = create_mapping(&svg.colors);
+                debug!("Recoloring svg using: {:?}", color_mapping);
+                std::fs::write(&cache_location_clone, svg.recolor(&color_mapping))
+                    .map_err(IconicError::from)
             })
             .await
-            .unwrap();
-        cache_location
+            .unwrap()?;
+        Ok(cache_location)
     }
 
-    pub async fn load_built_in_bottom_icon(&self) -> PathBuf {
+    // None if the folder could not be styled and there is no built-in folder in that color either
+    pub async fn load_built_in_bottom_icon(&self) -> Option<FolderSource> {
         let imp = self.imp();
         let current_set_accent_color = imp.settings.string("selected-accent-color");
         let folder_color_name = match current_set_accent_color.as_str() {
             "None" => self.get_accent_color_and_dialog(),
             x => x.to_string(),
         };
-        let folder_path = PathBuf::from(format!(
-            "/app/share/folder_icon/folders/folder_{}.svg",
-            folder_color_name
-        ));
-        folder_path
+        match self
+            .style_folder_for_color(&folder_color_name, "style_folder.svg")
+            .await
+        {
+            Ok(source) => Some(source),
+            Err(e) => {
+                self.show_iconic_error(&e);
+                FolderStyle::built_in()
+                    .colors
+                    .get(&folder_color_name)
+                    .cloned()
+            }
+        }
+    }
+
+    /* The folder of the current folder style in one of the accent colors.
+    If the style has no SVG for the color, the template gets colors that match the accent color */
+    pub async fn style_folder_for_color(
+        &self,
+        color_name: &str,
+        file_name: &str,
+    ) -> Result<FolderSource, IconicError> {
+        let imp = self.imp();
+        if let Some(source) = imp.folder_style.borrow().color_source(color_name) {
+            return Ok(source.clone());
+        }
+        let invalid = |reason: String| IconicError::InvalidFolderStyle {
+            style: imp.folder_style.borrow().id.clone(),
+            reason,
+        };
+        let accent_color = imp
+            .default_color
+            .borrow()
+            .get(color_name)
+            .cloned()
+            .ok_or_else(|| invalid(format!("Unknown folder color: {}", color_name)))?;
+        let (primary, secondary) = RecolorableSvg::derive_folder_colors(&RecolorableSvg::to_hex(
+            (accent_color.red() * 255.0) as u8,
+            (accent_color.green() * 255.0) as u8,
+            (accent_color.blue() * 255.0) as u8,
+        ))
+        .ok_or_else(|| invalid(String::from("Could not create folder colors")))?;
+        self.write_custom_folder(primary, secondary, file_name)
+            .await
+            .map(FolderSource::Path)
     }
 
     pub async fn paste_from_clipboard(&self) {
         let clipboard = self.clipboard();
         let imp = self.imp();
         let thumbnail_size: i32 = imp.settings.get("thumbnail-size");
+        let svg_render_size: i32 = imp.settings.get("svg-render-size");
 
         match clipboard
             .read_future(&["image/svg+xml"], glib::Priority::DEFAULT)
             .await
         {
-            Ok((stream, _mime)) => self.clipboard_load_svg(Some(stream)).await,
+            Ok((stream, _mime)) => self.clipboard_load_svg(stream).await,
             Err(_) => match clipboard.read_texture_future().await {
                 Ok(Some(texture)) => {
                     let top_file_selected = self.top_or_bottom_popup().await;
                     imp.stack.set_visible_child_name("stack_loading_page");
-
-                    let png_texture = texture.save_to_tiff_bytes();
-                    let image =
-                        image::load_from_memory_with_format(&png_texture, image::ImageFormat::Tiff)
-                            .unwrap();
-                    match top_file_selected {
-                        Some(true) => {
-                            let iconic_file = RUNTIME
-                                .spawn_blocking(move || {
-                                    File::from_image(image, thumbnail_size, "pasted")
-                                })
-                                .await
-                                .unwrap();
-                            match self.store_top_image_in_cache(&iconic_file, None) {
-                                Err(x) => {
-                                    self.show_error_popup(&x.to_string(), true, None);
-                                }
-                                _ => (),
-                            };
-                            imp.top_image_file.lock().unwrap().replace(iconic_file);
-                        }
-                        _ => {
-                            imp.temp_image_loaded.replace(true);
-                            imp.bottom_image_file.lock().unwrap().replace(
-                                RUNTIME
-                                    .spawn_blocking(move || {
-                                        File::from_image(image.clone(), thumbnail_size, "pasted")
-                                    })
-                                    .await
-                                    .unwrap(),
-                            );
-                        }
+                    let change_top_icon = top_file_selected == Some(true);
+                    if !change_top_icon {
+                        imp.temp_image_loaded.replace(true);
                     }
-                    self.check_icon_update();
+                    self.new_iconic_file_from_bytes(
+                        texture.save_to_tiff_bytes(),
+                        Some(String::from("image/tiff")),
+                        String::from("pasted.tiff"),
+                        svg_render_size,
+                        thumbnail_size,
+                        change_top_icon,
+                    )
+                    .await;
                 }
                 Ok(None) => {
                     warn!("No texture found");
@@ -145,7 +324,7 @@ impl GtkTestWindow {
         };
     }
 
-    pub async fn clipboard_load_svg(&self, stream: Option<gio::InputStream>) {
+    pub async fn clipboard_load_svg(&self, stream: gio::InputStream) {
         let imp = self.imp();
         let top_file = match self.top_or_bottom_popup().await {
             Some(true) => true,
@@ -153,34 +332,22 @@ impl GtkTestWindow {
         };
         let thumbnail_size: i32 = imp.settings.get("thumbnail-size");
         let svg_render_size: i32 = imp.settings.get("svg-render-size");
-        let none_file: Option<&gio::File> = None;
-        let none_cancelable: Option<&Cancellable> = None;
-        let loader = rsvg::Loader::new()
-            .read_stream(&stream.unwrap(), none_file, none_cancelable)
-            .unwrap();
-        let surface =
-            cairo::ImageSurface::create(cairo::Format::ARgb32, svg_render_size, svg_render_size)
-                .unwrap();
-        let cr = cairo::Context::new(&surface).expect("Failed to create a cairo context");
-        let renderer = rsvg::CairoRenderer::new(&loader);
-        renderer
-            .render_document(
-                &cr,
-                &cairo::Rectangle::new(
-                    0.0,
-                    0.0,
-                    f64::from(svg_render_size),
-                    f64::from(svg_render_size),
-                ),
+        let output = MemoryOutputStream::new_resizable();
+        if let Err(e) = output
+            .splice_future(
+                &stream,
+                OutputStreamSpliceFlags::CLOSE_SOURCE | OutputStreamSpliceFlags::CLOSE_TARGET,
+                glib::Priority::DEFAULT,
             )
-            .unwrap();
-        let cache_path = env::var("XDG_CACHE_HOME").unwrap();
-        let clipboard_path = PathBuf::from(format!("{}/clipboard.png", cache_path));
-        let mut stream = std::fs::File::create(&clipboard_path).unwrap();
-        surface.write_to_png(&mut stream).unwrap();
-        self.new_iconic_file_creation(
-            None,
-            Some(clipboard_path),
+            .await
+        {
+            self.show_iconic_error(&e.into());
+            return;
+        }
+        self.new_iconic_file_from_bytes(
+            output.steal_as_bytes(),
+            Some(String::from("image/svg+xml")),
+            String::from("pasted.svg"),
             svg_render_size,
             thumbnail_size,
             top_file,
@@ -198,12 +365,16 @@ impl GtkTestWindow {
             match file.query_info("standard::", FileQueryInfoFlags::NONE, Cancellable::NONE) {
                 Ok(x) => x,
                 Err(e) => {
-                    self.show_error_popup(&e.to_string(), true, Some(Box::new(e)));
+                    self.show_iconic_error(&e.into());
                     return;
                 }
             };
 
         debug!("file name: {:?}", file_info.name());
+        if file_info.content_type().as_deref() == Some("application/x-desktop") {
+            self.load_desktop_file(file).await;
+            return;
+        }
         let mime_type: Option<String> = match file_info.content_type() {
             Some(x) => {
                 let sub_string: Vec<&str> = x.split("/").collect();
@@ -212,8 +383,12 @@ impl GtkTestWindow {
             None => None,
         };
         debug!("file type: {:?}", mime_type);
+        // Logos often come as PDF or EPS, these are documents instead of images
+        let is_document = file_info
+            .content_type()
+            .is_some_and(|x| DOCUMENT_MIME_TYPES.contains(&x.as_str()));
         match mime_type {
-            Some(x) if x == String::from("image") => {
+            Some(x) if x == "image" || is_document => {
                 let top_file_selected = self.top_or_bottom_popup().await;
                 imp.stack.set_visible_child_name("stack_loading_page");
                 match top_file_selected {
@@ -243,14 +418,15 @@ impl GtkTestWindow {
                 };
             }
             _ => {
-                self.show_error_popup(&gettext("Unsupported file type"), true, None);
+                let content_type = file_info.content_type().unwrap_or_default();
+                self.show_iconic_error(&IconicError::UnsupportedFormat(content_type.to_string()));
             }
         }
 
         self.check_icon_update();
     }
 
-    pub async fn open_save_file_dialog(&self) -> Result<bool, Box<dyn Error + '_>> {
+    pub async fn open_save_file_dialog(&self) -> Result<bool, IconicError> {
         let imp = self.imp();
         if !imp.save_button.is_sensitive() {
             imp.toast_overlay
@@ -259,7 +435,12 @@ impl GtkTestWindow {
         };
         let file_name = format!(
             "folder-{}.png",
-            imp.top_image_file.lock()?.as_ref().unwrap().filename
+            imp.top_image_file
+                .lock()
+                .unwrap()
+                .as_ref()
+                .unwrap()
+                .filename
         );
         let file_chooser = gtk::FileDialog::builder()
             .initial_name(file_name)
@@ -268,8 +449,9 @@ impl GtkTestWindow {
         self.imp().stack.set_visible_child_name("stack_saving_page");
         match file_chooser.save_future(Some(self)).await {
             Ok(file) => {
-                let saved_file = self.save_file(file).await?;
+                let saved_file = self.save_file(file).await;
                 self.imp().stack.set_visible_child_name("stack_main_page");
+                let saved_file = saved_file?;
                 imp.toast_overlay.add_toast(
                     adw::Toast::builder()
                         .button_label(gettext("Open Folder"))
@@ -290,7 +472,7 @@ impl GtkTestWindow {
                     _ => {
                         imp.image_saved.replace(false);
                         imp.save_button.set_sensitive(true);
-                        return Err(Box::new(e));
+                        return Err(IconicError::SaveFailed(e.to_string()));
                     }
                 };
             }
@@ -298,40 +480,57 @@ impl GtkTestWindow {
         Ok(true)
     }
 
-    pub async fn save_file(&self, file: gio::File) -> Result<bool, Box<dyn Error + '_>> {
+    /* Usually cached when they were loaded, but the cache might have been disabled or the image removed
+    since. Without them the icon can not be regenerated */
+    pub fn cache_generation_images(&self) {
+        if let Err(err) = self.ensure_cached(false) {
+            warn!("Could not cache top image: {}", err);
+        }
+        if self.has_custom_bottom() {
+            if let Err(err) = self.ensure_cached(true) {
+                warn!("Could not cache bottom image: {}", err);
+            }
+        }
+    }
+
+    pub async fn save_file(&self, file: gio::File) -> Result<bool, IconicError> {
         let imp = self.imp();
-        imp.saved_file.lock()?.replace(file.clone());
+        imp.saved_file.lock().unwrap().replace(file.clone());
+        self.cache_generation_images();
         let base_image = imp
             .bottom_image_file
-            .lock()?
-            .as_ref()
+            .lock()
             .unwrap()
-            .dynamic_image
-            .clone();
-        let mut top_image = imp
-            .top_image_file
-            .lock()?
             .as_ref()
             .unwrap()
-            .thumbnail
+            .dynamic_image
             .clone();
-        if imp.monochrome_switch.state() {
-            top_image = self.to_monochrome(
-                top_image,
-                imp.threshold_scale.value() as u8,
-                imp.monochrome_color.rgba(),
-            );
-        }
+        let top_layer = {
+            let top_file = imp.top_image_file.lock().unwrap();
+            let top_file = top_file.as_ref().unwrap();
+            TopLayer::from_file(top_file, &top_file.dynamic_image)
+        };
         let generated_image = self
-            .generate_image(base_image, top_image, imageops::FilterType::Gaussian)
+            .generate_image(
+                base_image,
+                top_layer,
+                self.current_monochrome(),
+                self.resampling(),
+            )
             .await;
-        let _ = RUNTIME
-            .spawn_blocking(move || {
-                generated_image.save_with_format(file.path().unwrap(), ImageFormat::Png)
-            })
-            .await?;
+        let embed_profile = imp.settings.boolean("embed-color-profile");
+        let png = RUNTIME
+            .spawn_blocking(move || color_profile::encode_png(&generated_image, embed_profile))
+            .await
+            .unwrap()
+            .map_err(|err| IconicError::SaveFailed(err.to_string()))?;
+        // Written through GIO, so the file picker can also return a location without a local path
+        file.replace_contents_future(png, None, false, FileCreateFlags::REPLACE_DESTINATION)
+            .await
+            .map_err(|(_, err)| IconicError::SaveFailed(err.to_string()))?;
         imp.image_saved.replace(true);
         imp.save_button.set_sensitive(false);
+        self.remember_top_image_properties();
         Ok(true)
     }
 
@@ -358,6 +557,69 @@ impl GtkTestWindow {
         self.check_icon_update();
     }
 
+    // Uses the icon of an installed app as top image
+    pub async fn load_top_from_installed_app(&self) {
+        let apps = AppIcon::installed_apps();
+        if let Some(app_info) = self.app_picker_popup(apps).await {
+            self.load_app_icon(&app_info).await;
+        }
+    }
+
+    pub async fn load_desktop_file(&self, file: gio::File) {
+        let Some(path) = file.path() else {
+            self.show_iconic_error(&IconicError::UnsupportedFormat(String::from(
+                "application/x-desktop",
+            )));
+            return;
+        };
+        match DesktopAppInfo::from_filename(&path) {
+            Some(app_info) => self.load_app_icon(app_info.upcast_ref()).await,
+            None => self.show_iconic_error(&IconicError::DecodeFailed {
+                format: String::from("Desktop file"),
+                reason: format!("\"{}\" is not a valid desktop file", path.display()),
+            }),
+        }
+    }
+
+    /* Finds the icon of the app in the icon theme and loads it as top image.
+    Symbolic icons are a single color, so monochrome is turned on for them and off for colored icons */
+    pub async fn load_app_icon(&self, app_info: &gio::AppInfo) {
+        let imp = self.imp();
+        let svg_render_size: i32 = imp.settings.get("svg-render-size");
+        let thumbnail_size: i32 = imp.settings.get("thumbnail-size");
+        let icon_theme = gtk::IconTheme::for_display(&self.display());
+        let app_icon = match AppIcon::from_app_info(app_info, &icon_theme, svg_render_size) {
+            Ok(x) => x,
+            Err(e) => {
+                self.show_iconic_error(&e);
+                return;
+            }
+        };
+        debug!(
+            "Icon of {}: {:?}, symbolic: {}",
+            app_icon.app_name,
+            app_icon.file.path(),
+            app_icon.is_symbolic
+        );
+        if imp.stack.visible_child_name() == Some("stack_welcome_page".into()) {
+            imp.stack.set_visible_child_name("stack_loading_page");
+        }
+        if self
+            .new_iconic_file_creation(
+                Some(app_icon.file),
+                None,
+                svg_render_size,
+                thumbnail_size,
+                true,
+            )
+            .await
+            .is_some()
+        {
+            imp.monochrome_switch.set_active(app_icon.is_symbolic);
+        }
+        self.check_icon_update();
+    }
+
     pub async fn load_temp_folder_icon(&self) {
         let imp = self.imp();
         let thumbnail_size: i32 = imp.settings.get("thumbnail-size");
@@ -389,6 +651,31 @@ impl GtkTestWindow {
         .await;
     }
 
+    // Bundled folders are loaded from memory, without going through the filesystem
+    pub async fn load_folder_source(&self, source: &FolderSource) {
+        let resource = match source {
+            FolderSource::Path(path) => {
+                return self.load_folder_icon(&path.to_string_lossy()).await
+            }
+            FolderSource::Resource(_) => source.clone(),
+        };
+        let imp = self.imp();
+        let svg_render_size: i32 = imp.settings.get("svg-render-size");
+        let thumbnail_size: i32 = imp.settings.get("thumbnail-size");
+        let resampling = self.resampling();
+        match RUNTIME
+            .spawn_blocking(move || resource.to_file(svg_render_size, thumbnail_size, resampling))
+            .await
+            .unwrap()
+        {
+            Ok(file) => {
+                imp.bottom_image_file.lock().unwrap().replace(file);
+            }
+            Err(e) => self.show_iconic_error(&e),
+        };
+        self.check_icon_update();
+    }
+
     pub async fn load_top_file(&self, filename: gio::File) {
         let imp = self.imp();
         if imp.stack.visible_child_name() == Some("stack_welcome_page".into()) {
@@ -400,9 +687,8 @@ impl GtkTestWindow {
             .await;
     }
 
-    // Creates a new folder_icon::File from a gio::file, path or dynamicimage.
+    // Creates a new folder_icon::File from a gio::file or path.
     // Will show an error if none are provided
-    // TODO rewrite this, it is REALLY confusing
     pub async fn new_iconic_file_creation(
         &self,
         file: Option<gio::File>,
@@ -411,78 +697,105 @@ impl GtkTestWindow {
         thumbnail_render_size: i32,
         change_top_icon: bool,
     ) -> Option<File> {
-        let imp = self.imp();
-        let new_file = if let Some(file_temp) = file {
-            let file_temp_clone = file_temp.clone();
-            let iconic_file = match RUNTIME
-                .spawn_blocking(move || {
-                    File::new(file_temp_clone, svg_render_size, thumbnail_render_size)
-                        .map_err(|err| err.to_string())
-                })
-                .await
-                .unwrap()
-            {
-                Ok(x) => x,
-                Err(e) => {
-                    self.show_error_popup(&e.to_string(), true, None);
-                    return None;
-                }
-            };
-            if change_top_icon {
-                match self.store_top_image_in_cache(&iconic_file, Some(&file_temp)) {
-                    Err(x) => {
-                        self.show_error_popup("", true, Some(x));
-                    }
-                    _ => (),
-                };
+        let file = match (file, path) {
+            (Some(file), _) => file,
+            (None, Some(path)) => gio::File::for_path(path),
+            (None, None) => {
+                self.show_error_popup(
+                    &gettext("No file or path found, this is probably not your fault."),
+                    true,
+                    None,
+                );
+                self.check_icon_update();
+                return None;
             }
-            Some(iconic_file)
-        } else if let Some(path_temp) = path {
-            let file_temp = gio::File::for_path(path_temp);
-            let file_temp_clone = file_temp.clone();
-            let iconic_file = match RUNTIME
-                .spawn_blocking(move || {
-                    File::new(file_temp_clone, svg_render_size, thumbnail_render_size)
-                        .map_err(|err| err.to_string())
-                })
-                .await
-                .unwrap()
-            {
-                Ok(x) => x,
-                Err(e) => {
-                    self.show_error_popup(&e.to_string(), true, None);
-                    return None;
-                }
-            };
-            if change_top_icon {
-                match self.store_top_image_in_cache(&iconic_file, Some(&file_temp)) {
-                    Err(x) => {
-                        self.show_error_popup("", true, Some(x));
-                    }
-                    _ => (),
-                };
+        };
+        // Read through GIO, so files without a local path like sftp:// or portal URIs work too
+        let file_info = match file
+            .query_info_future(
+                "standard::",
+                FileQueryInfoFlags::NONE,
+                glib::Priority::DEFAULT,
+            )
+            .await
+        {
+            Ok(x) => x,
+            Err(e) => {
+                self.show_iconic_error(&e.into());
+                return None;
             }
-            Some(iconic_file)
-        } else {
-            self.show_error_popup(
-                &gettext("No file or path found, this is probably not your fault."),
-                true,
-                None,
-            );
-            None
         };
-        match new_file.clone() {
-            Some(file) => {
-                match change_top_icon {
-                    true => imp.top_image_file.lock().unwrap().replace(file),
-                    false => imp.bottom_image_file.lock().unwrap().replace(file),
-                };
-                self.check_icon_update();
+        let bytes = match file.load_bytes_future().await {
+            Ok((bytes, _)) => bytes,
+            Err(e) => {
+                self.show_iconic_error(&e.into());
+                return None;
             }
-            None => {
-                self.check_icon_update();
+        };
+        self.new_iconic_file_from_bytes(
+            bytes,
+            file_info.content_type().map(|x| x.to_string()),
+            file_info.name().to_string_lossy().to_string(),
+            svg_render_size,
+            thumbnail_render_size,
+            change_top_icon,
+        )
+        .await
+    }
+
+    // Creates a new folder_icon::File from data in memory and uses it as top or bottom image
+    pub async fn new_iconic_file_from_bytes(
+        &self,
+        bytes: glib::Bytes,
+        mime_type: Option<String>,
+        file_name: String,
+        svg_render_size: i32,
+        thumbnail_render_size: i32,
+        change_top_icon: bool,
+    ) -> Option<File> {
+        let imp = self.imp();
+        let bytes_clone = bytes.clone();
+        let resampling = self.resampling();
+        let mut iconic_file = match RUNTIME
+            .spawn_blocking(move || {
+                File::from_bytes(
+                    &bytes_clone,
+                    mime_type.as_deref(),
+                    &file_name,
+                    svg_render_size,
+                    thumbnail_render_size,
+                    resampling,
+                )
+            })
+            .await
+            .unwrap()
+        {
+            Ok(x) => x,
+            Err(e) => {
+                self.show_iconic_error(&e);
+                return None;
+            }
+        };
+        if change_top_icon {
+            match self.store_top_image_in_cache(&iconic_file, Some(&bytes), false) {
+                Ok(Some(hash)) => iconic_file.hash = hash,
+                Ok(None) => (),
+                Err(error) => self.show_iconic_error(&error),
             }
         }
-        new_file
+        match change_top_icon {
+            true => imp
+                .top_image_file
+                .lock()
+                .unwrap()
+                .replace(iconic_file.clone()),
+            false => imp
+                .bottom_image_file
+                .lock()
+                .unwrap()
+                .replace(iconic_file.clone()),
+        };
+        self.check_icon_update();
+        Some(iconic_file)
     }
 }
@@ -0,0 +1,212 @@
+use adw::{prelude::*, subclass::prelude::*};
+use gettextrs::gettext;
+use gtk::gdk::RGBA;
+use gtk::glib;
+use log::*;
+
+use crate::objects::file::File;
+use crate::objects::iconic_error::IconicError;
+use crate::objects::resampling::Resampling;
+use crate::objects::top_image_cache::{CacheEntry, TopImageCache};
+use crate::{GtkTestWindow, RUNTIME};
+
+// Pinned top images are always shown, the recent ones fill up the rest
+const GALLERY_LIMIT: usize = 12;
+const GALLERY_THUMBNAIL_SIZE: i32 = 64;
+
+impl GtkTestWindow {
+    // Shows the pinned and recently used top images from the top cache on the welcome page
+    pub fn load_top_image_gallery(&self) {
+        let imp = self.imp();
+        let gallery = &imp.top_image_gallery;
+        while let Some(child) = gallery.first_child() {
+            gallery.remove(&child);
+        }
+        let entries = match imp.settings.boolean("store-top-in-cache") {
+            true => TopImageCache::open(&self.get_cache_path())
+                .gallery(&Self::get_data_path(), GALLERY_LIMIT),
+            false => vec![],
+        };
+        imp.top_image_gallery_clamp.set_visible(!entries.is_empty());
+        for entry in entries {
+            gallery.append(&self.create_gallery_item(&entry));
+        }
+    }
+
+    fn create_gallery_item(&self, entry: &CacheEntry) -> gtk::Overlay {
+        let thumbnail = gtk::Image::from_icon_name("image-x-generic-symbolic");
+        thumbnail.set_pixel_size(GALLERY_THUMBNAIL_SIZE);
+        self.load_gallery_thumbnail(entry, &thumbnail);
+        let button = gtk::Button::builder()
+            .child(&thumbnail)
+            .tooltip_text(entry.original_name.as_deref().unwrap_or(&entry.name))
+            .build();
+        button.add_css_class("flat");
+
+        let pin = gtk::ToggleButton::builder()
+            .icon_name("view-pin-symbolic")
+            .tooltip_text(match entry.pinned {
+                true => gettext("Unpin"),
+                false => gettext("Pin"),
+            })
+            .active(entry.pinned)
+            .halign(gtk::Align::End)
+            .valign(gtk::Align::Start)
+            .build();
+        pin.add_css_class("circular");
+        pin.add_css_class("osd");
+        // Only show the pin button of unpinned images while hovering, like a close button of a tab
+        if !entry.pinned {
+            pin.set_opacity(0.0);
+            let hover = gtk::EventControllerMotion::new();
+            hover.connect_enter(glib::clone!(
+                #[weak]
+                pin,
+                move |_, _, _| pin.set_opacity(1.0)
+            ));
+            hover.connect_leave(glib::clone!(
+                #[weak]
+                pin,
+                move |_| pin.set_opacity(0.0)
+            ));
+            button.add_controller(hover);
+        }
+
+        let overlay = gtk::Overlay::builder().child(&button).build();
+        overlay.add_overlay(&pin);
+
+        let name = entry.name.clone();
+        pin.connect_toggled(glib::clone!(
+            #[weak (rename_to = win)]
+            self,
+            move |pin| {
+                let cache = TopImageCache::open(&win.get_cache_path());
+                cache.set_pinned(&name, pin.is_active());
+                cache.save();
+                // Reloaded later, so the button is not removed while its signal is still running
+                glib::idle_add_local_once(glib::clone!(
+                    #[weak]
+                    win,
+                    move || win.load_top_image_gallery()
+                ));
+            }
+        ));
+        let entry = entry.clone();
+        button.connect_clicked(glib::clone!(
+            #[weak (rename_to = win)]
+            self,
+            move |_| {
+                glib::spawn_future_local(glib::clone!(
+                    #[weak]
+                    win,
+                    #[strong]
+                    entry,
+                    async move {
+                        win.load_gallery_entry(&entry).await;
+                    }
+                ));
+            }
+        ));
+        overlay
+    }
+
+    // Cached top images have no extension, so they are read like dropped files
+    fn load_gallery_thumbnail(&self, entry: &CacheEntry, image: &gtk::Image) {
+        let path = entry.path.clone();
+        glib::spawn_future_local(glib::clone!(
+            #[weak (rename_to = win)]
+            self,
+            #[weak]
+            image,
+            async move {
+                let thumbnail = RUNTIME
+                    .spawn_blocking(move || {
+                        File::from_path(
+                            path,
+                            GALLERY_THUMBNAIL_SIZE,
+                            GALLERY_THUMBNAIL_SIZE,
+                            Resampling::Lanczos3,
+                        )
+                        .map(|file| file.thumbnail)
+                    })
+                    .await
+                    .unwrap();
+                match thumbnail {
+                    Ok(thumbnail) => {
+                        image.set_paintable(Some(&win.dynamic_image_to_texture(&thumbnail)))
+                    }
+                    Err(err) => debug!("No thumbnail for gallery image: {}", err),
+                }
+            }
+        ));
+    }
+
+    // Loads a top image from the gallery, placed the way it was last saved
+    async fn load_gallery_entry(&self, entry: &CacheEntry) {
+        let imp = self.imp();
+        imp.stack.set_visible_child_name("stack_loading_page");
+        let svg_render_size: i32 = imp.settings.get("svg-render-size");
+        let size: i32 = imp.settings.get("thumbnail-size");
+        if self
+            .new_iconic_file_creation(None, Some(entry.path.clone()), svg_render_size, size, true)
+            .await
+            .is_none()
+        {
+            imp.stack.set_visible_child_name("stack_welcome_page");
+            return;
+        }
+        let Some(properties) = &entry.properties else {
+            return;
+        };
+        // Indexed like the parts of the file name of a generated icon
+        let properties: Vec<&str> = std::iter::once("folder_new")
+            .chain(properties.iter().map(String::as_str))
+            .collect();
+        if let Err(err) = self.restore_gallery_properties(properties) {
+            warn!(
+                "Could not restore the properties of {}: {}",
+                entry.name, err
+            );
+        }
+    }
+
+    fn restore_gallery_properties(&self, properties: Vec<&str>) -> Result<(), IconicError> {
+        self.set_properties(properties.clone(), self.get_default_color())?;
+        // Unlike regeneration, a custom monochrome color is kept instead of following the accent color
+        if properties.get(11) == Some(&"false") {
+            self.imp().monochrome_color.set_rgba(&RGBA::new(
+                Self::parse_property(&properties, 7)?,
+                Self::parse_property(&properties, 8)?,
+                Self::parse_property(&properties, 9)?,
+                1.0,
+            ));
+        }
+        Ok(())
+    }
+
+    // Called when an icon is saved, so the gallery opens the top image the same way next time
+    pub fn remember_top_image_properties(&self) {
+        let imp = self.imp();
+        if !imp.settings.boolean("store-top-in-cache") {
+            return;
+        }
+        let Some(hash) = imp
+            .top_image_file
+            .lock()
+            .unwrap()
+            .as_ref()
+            .filter(|file| file.dynamic_image.width() > 1)
+            .map(|file| file.hash.to_string())
+        else {
+            return;
+        };
+        let cache = TopImageCache::open(&self.get_cache_path());
+        if !cache.entry_path(&hash).exists() {
+            return;
+        }
+        cache.set_properties(&hash, &self.image_properties());
+        cache.touch(&hash);
+        cache.save();
+        self.load_top_image_gallery();
+    }
+}
@@ -1,76 +1,170 @@
 use adw::{prelude::*, subclass::prelude::*};
 use gtk::gdk;
 use image::*;
+use log::*;
+use resvg::tiny_skia::{Pixmap, Transform};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
+use crate::objects::file::{File, SvgTree};
+use crate::objects::pixels;
+use crate::objects::render_cache::RenderCache;
+use crate::objects::resampling::Resampling;
 use crate::GtkTestWindow;
 use crate::RUNTIME;
 
+/* The top image as it goes into generate_image. SVGs keep their tree, so they are rendered once
+at exactly the size and rotation they end up with, instead of resizing a bitmap again.
+The hash is used to find earlier results in the render cache */
+#[derive(Debug, Clone, PartialEq)]
+pub enum TopLayer {
+    Raster { image: DynamicImage, hash: u64 },
+    Vector { svg_tree: SvgTree, hash: u64 },
+}
+
+impl TopLayer {
+    // The bitmap is used for images that are not SVG, like the thumbnail for the preview
+    pub fn from_file(file: &File, bitmap: &DynamicImage) -> Self {
+        match &file.svg_tree {
+            Some(svg_tree) => Self::Vector {
+                svg_tree: svg_tree.clone(),
+                hash: file.hash.as_u64(),
+            },
+            // The thumbnail and the full image of a file share the hash, so the size tells them apart
+            None => {
+                let size = (bitmap.width() as u64) << 32 | bitmap.height() as u64;
+                Self::Raster {
+                    image: bitmap.clone(),
+                    hash: file.hash.as_u64() ^ size,
+                }
+            }
+        }
+    }
+}
+
+// The monochrome settings, so the filter can be applied away from the main thread
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Monochrome {
+    pub threshold: u8,
+    pub color: gdk::RGBA,
+    pub invert: bool,
+}
+
+impl Monochrome {
+    fn cache_key(&self, source: u64) -> u64 {
+        let color = GtkTestWindow::create_colored_pixel(self.color, 255).0;
+        RenderCache::monochrome_key(
+            source,
+            self.threshold,
+            [color[0], color[1], color[2]],
+            self.invert,
+        )
+    }
+}
+
+// Renders of the preview are numbered, a render stops as soon as a newer one has been requested
+#[derive(Debug, Clone)]
+pub struct RenderTicket {
+    number: u64,
+    latest: Arc<AtomicU64>,
+}
+
+impl RenderTicket {
+    pub fn is_stale(&self) -> bool {
+        self.latest.load(Ordering::SeqCst) != self.number
+    }
+}
+
 impl GtkTestWindow {
+    /* Slider changes come in much faster than previews can be rendered. Only one preview is rendered at a time,
+    requests that come in meanwhile stop that render and the newest one is rendered after it */
     pub async fn render_to_screen(&self) {
         let imp = self.imp();
-        let base = imp
-            .bottom_image_file
-            .lock()
-            .unwrap()
-            .as_ref()
-            .unwrap()
-            .thumbnail
-            .clone();
-        let mut top_image = imp
-            .top_image_file
-            .lock()
-            .unwrap()
-            .as_ref()
-            .unwrap()
-            .thumbnail
-            .clone();
-        if imp.monochrome_switch.state() {
-            top_image = self.to_monochrome(
-                top_image,
-                imp.threshold_scale.value() as u8,
-                imp.monochrome_color.rgba(),
-            );
+        imp.render_requests.fetch_add(1, Ordering::SeqCst);
+        if imp.render_running.replace(true) {
+            return;
         }
-        let texture = self.dynamic_image_to_texture(
-            &self
-                .generate_image(base, top_image, imageops::FilterType::Nearest)
-                .await,
-        );
+        let mut previous_cancelled = false;
+        loop {
+            let ticket = RenderTicket {
+                number: imp.render_requests.load(Ordering::SeqCst),
+                latest: imp.render_requests.clone(),
+            };
+            // A render that is never allowed to finish would freeze the preview while a slider is dragged
+            let cancellable = match previous_cancelled {
+                true => None,
+                false => Some(ticket.clone()),
+            };
+            previous_cancelled = !self.render_preview(cancellable).await;
+            if !ticket.is_stale() {
+                break;
+            }
+        }
+        imp.render_running.replace(false);
+    }
+
+    // Returns false when the render was stopped because a newer one was requested
+    async fn render_preview(&self, ticket: Option<RenderTicket>) -> bool {
+        let imp = self.imp();
+        let base = match imp.bottom_image_file.lock().unwrap().as_ref() {
+            Some(file) => file.thumbnail.clone(),
+            None => return true,
+        };
+        let top_layer = match imp.top_image_file.lock().unwrap().as_ref() {
+            Some(top_file) => TopLayer::from_file(top_file, &top_file.thumbnail),
+            None => return true,
+        };
+        let Some(mut preview) = self
+            .generate_image_cancellable(
+                base,
+                top_layer,
+                self.current_monochrome(),
+                self.resampling(),
+                ticket,
+            )
+            .await
+        else {
+            debug!("Preview render cancelled");
+            return false;
+        };
+        if imp.perspective_switch.is_active() {
+            self.draw_perspective_handles(&mut preview);
+        }
+        let texture = self.dynamic_image_to_texture(&preview);
         imp.image_view.set_paintable(Some(&texture));
         imp.image_view.queue_draw();
+        true
     }
 
-    pub fn to_monochrome(
-        &self,
-        image: DynamicImage,
-        threshold: u8,
-        color: gdk::RGBA,
-    ) -> DynamicImage {
-        // Convert the image to RGBA8
-        let rgba_img = image.to_rgba8();
-        // Define a threshold value
-        let threshold = threshold; // Adjust the threshold value as needed
-
-        // Create a new image buffer for the monochrome image
-        let mut mono_img: RgbaImage = ImageBuffer::new(rgba_img.width(), rgba_img.height());
-        let switch_state = self.imp().monochrome_invert.is_active();
-        // Apply the threshold to create a black and white image, keeping the alpha channel
-        for (x, y, pixel) in rgba_img.enumerate_pixels() {
-            let rgba = pixel.0;
-            let luma = 0.299 * rgba[0] as f32 + 0.587 * rgba[1] as f32 + 0.114 * rgba[2] as f32;
-            let threshold_reached = luma >= threshold as f32 && rgba[3] > 0;
-            let mono_pixel = match (threshold_reached, switch_state) {
-                (false, false) | (true, true) => Rgba([0u8, 0u8, 0u8, 0u8]), // Black
-                _ => self.create_colored_pixel(color, rgba[3]), // White with original alpha
-            };
-            mono_img.put_pixel(x, y, mono_pixel);
+    // The monochrome settings of the sliders, none if monochrome is turned off
+    pub fn current_monochrome(&self) -> Option<Monochrome> {
+        let imp = self.imp();
+        match imp.monochrome_switch.state() {
+            true => Some(Monochrome {
+                threshold: imp.threshold_scale.value() as u8,
+                color: imp.monochrome_color.rgba(),
+                invert: imp.monochrome_invert.is_active(),
+            }),
+            false => None,
         }
+    }
 
-        // Convert the monochrome RgbaImage to DynamicImage
-        DynamicImage::ImageRgba8(mono_img)
+    // The resampling filter from the preferences
+    pub fn resampling(&self) -> Resampling {
+        Resampling::from_id(&self.imp().settings.string("resampling-filter"))
     }
 
-    fn create_colored_pixel(&self, color: gdk::RGBA, original_alpha: u8) -> Rgba<u8> {
+    pub fn to_monochrome(image: DynamicImage, monochrome: Monochrome) -> DynamicImage {
+        let color = Self::create_colored_pixel(monochrome.color, 255).0;
+        DynamicImage::ImageRgba8(pixels::monochrome(
+            image.into_rgba8(),
+            monochrome.threshold,
+            [color[0], color[1], color[2]],
+            monochrome.invert,
+        ))
+    }
+
+    pub fn create_colored_pixel(color: gdk::RGBA, original_alpha: u8) -> Rgba<u8> {
         Rgba([
             (color.red() * 255.0) as u8,
             (color.green() * 255.0) as u8,
@@ -82,22 +176,119 @@ impl GtkTestWindow {
     pub async fn generate_image(
         &self,
         base_image: image::DynamicImage,
-        top_image: image::DynamicImage,
-        filter: imageops::FilterType,
+        top_layer: TopLayer,
+        monochrome: Option<Monochrome>,
+        resampling: Resampling,
     ) -> DynamicImage {
+        // Exports are full resolution, keeping them in the preview cache would only fill the memory
+        let cache = Arc::new(RenderCache::default());
+        self.render_image(base_image, top_layer, monochrome, resampling, None, cache)
+            .await
+            .unwrap()
+    }
+
+    // Returns none if the ticket became stale before the image was done
+    pub async fn generate_image_cancellable(
+        &self,
+        base_image: image::DynamicImage,
+        top_layer: TopLayer,
+        monochrome: Option<Monochrome>,
+        resampling: Resampling,
+        ticket: Option<RenderTicket>,
+    ) -> Option<DynamicImage> {
+        let cache = self.imp().render_cache.clone();
+        self.render_image(base_image, top_layer, monochrome, resampling, ticket, cache)
+            .await
+    }
+
+    async fn render_image(
+        &self,
+        base_image: image::DynamicImage,
+        top_layer: TopLayer,
+        monochrome: Option<Monochrome>,
+        resampling: Resampling,
+        ticket: Option<RenderTicket>,
+        cache: Arc<RenderCache>,
+    ) -> Option<DynamicImage> {
         let imp = self.imp();
         let coordinates = (
             (imp.x_scale.value() + 50.0) as i64,
             (imp.y_scale.value() + 50.0) as i64,
         );
         let scale: f32 = imp.size.value() as f32;
-        let texture = RUNTIME
+        let rotation: f32 = imp.rotation_scale.value() as f32;
+        let flip = (
+            imp.flip_horizontal_button.is_active(),
+            imp.flip_vertical_button.is_active(),
+        );
+        let corners = self.active_perspective_corners();
+        let is_stale = move || ticket.as_ref().is_some_and(|ticket| ticket.is_stale());
+        let (texture, top_bounds) = RUNTIME
             .spawn_blocking(move || {
-                let mut base = base_image;
-                let top = top_image;
+                let base = base_image;
                 let base_dimension: (i64, i64) =
                     ((base.dimensions().0).into(), (base.dimensions().1).into());
-                let top = GtkTestWindow::resize_image(top, base.dimensions(), scale, filter);
+                let top_size = GtkTestWindow::top_size(base.dimensions(), scale);
+                let top = match top_layer {
+                    // Monochrome works on the original pixels, before they are resampled
+                    TopLayer::Raster { image, hash } => {
+                        let (top, hash) = match monochrome {
+                            Some(monochrome) => {
+                                let key = monochrome.cache_key(hash);
+                                let top = cache.monochrome(key, || {
+                                    GtkTestWindow::to_monochrome(image, monochrome)
+                                });
+                                (top, key)
+                            }
+                            None => (image, hash),
+                        };
+                        if is_stale() {
+                            return None;
+                        }
+                        let key = RenderCache::transformed_key(
+                            hash,
+                            top_size,
+                            Some(resampling),
+                            flip,
+                            rotation,
+                        );
+                        cache.transformed(key, || {
+                            let top = GtkTestWindow::resize_image(
+                                top,
+                                base.dimensions(),
+                                scale,
+                                resampling,
+                            );
+                            let top = GtkTestWindow::flip_image(top, flip);
+                            GtkTestWindow::rotate_image(top, rotation)
+                        })
+                    }
+                    TopLayer::Vector { svg_tree, hash } => {
+                        let key =
+                            RenderCache::transformed_key(hash, top_size, None, flip, rotation);
+                        let top = cache.transformed(key, || {
+                            GtkTestWindow::render_vector(
+                                &svg_tree,
+                                base.dimensions(),
+                                scale,
+                                flip,
+                                rotation,
+                            )
+                        });
+                        if is_stale() {
+                            return None;
+                        }
+                        match monochrome {
+                            Some(monochrome) => cache.monochrome(monochrome.cache_key(key), || {
+                                GtkTestWindow::to_monochrome(top, monochrome)
+                            }),
+                            None => top,
+                        }
+                    }
+                };
+                if is_stale() {
+                    return None;
+                }
                 let top_dimension: (i64, i64) = (
                     (top.dimensions().0 / 2).into(),
                     (top.dimensions().1 / 2).into(),
@@ -106,32 +297,176 @@ impl GtkTestWindow {
                     ((base_dimension.0 * coordinates.0) / 100) - top_dimension.0,
                     ((base_dimension.1 * coordinates.1) / 100) - top_dimension.1,
                 );
-                imageops::overlay(
+                // Where the top image is before the perspective is applied, used to place the corner handles
+                let top_bounds = (
+                    final_coordinates.0 as f32 / base_dimension.0 as f32,
+                    final_coordinates.1 as f32 / base_dimension.1 as f32,
+                    top.width() as f32 / base_dimension.0 as f32,
+                    top.height() as f32 / base_dimension.1 as f32,
+                );
+                let (top, warp_offset) = GtkTestWindow::perspective_warp(top, corners);
+                let mut base = base.into_rgba8();
+                pixels::overlay_linear(
                     &mut base,
-                    &top,
-                    final_coordinates.0.into(),
-                    final_coordinates.1.into(),
+                    &top.into_rgba8(),
+                    final_coordinates.0 + warp_offset.0,
+                    final_coordinates.1 + warp_offset.1,
                 );
-                base
+                Some((DynamicImage::ImageRgba8(base), top_bounds))
             })
             .await
-            .unwrap();
+            .unwrap()?;
 
+        imp.top_image_bounds.replace(top_bounds);
         imp.generated_image.replace(Some(texture.clone()));
-        texture
+        Some(texture)
+    }
+
+    // The size of the box the top image is fitted in, the size slider makes it smaller
+    pub fn top_size(dimensions: (u32, u32), slider_position: f32) -> (u32, u32) {
+        let scale_factor: f32 = (slider_position + 10.0) / 10.0;
+        (
+            (dimensions.0 as f32 / scale_factor) as u32,
+            (dimensions.1 as f32 / scale_factor) as u32,
+        )
     }
 
     pub fn resize_image(
         image: DynamicImage,
         dimensions: (u32, u32),
         slider_position: f32,
-        filter: imageops::FilterType,
+        resampling: Resampling,
     ) -> DynamicImage {
-        let width: f32 = dimensions.0 as f32;
-        let height: f32 = dimensions.1 as f32;
+        let (new_width, new_height) = Self::top_size(dimensions, slider_position);
+        resampling.resize(&image, new_width, new_height)
+    }
+
+    /* Renders the SVG at the size resize_image would give it, flipped and rotated like flip_image and
+    rotate_image do, all in one transform so the shapes are only rasterized once */
+    pub fn render_vector(
+        svg_tree: &SvgTree,
+        dimensions: (u32, u32),
+        slider_position: f32,
+        flip: (bool, bool),
+        degrees: f32,
+    ) -> DynamicImage {
+        let tree_size = svg_tree.0.size();
         let scale_factor: f32 = (slider_position + 10.0) / 10.0;
-        let new_width: u32 = (width / scale_factor) as u32;
-        let new_height: u32 = (height / scale_factor) as u32;
-        image.resize(new_width, new_height, filter)
+        let scale = (dimensions.0 as f32 / scale_factor / tree_size.width())
+            .min(dimensions.1 as f32 / scale_factor / tree_size.height());
+        let width = (tree_size.width() * scale).round().max(1.0);
+        let height = (tree_size.height() * scale).round().max(1.0);
+
+        let degrees = degrees.rem_euclid(360.0);
+        // Exact values for quarter turns, so the canvas does not grow by a rounding error
+        let (sin, cos) = if degrees == 0.0 {
+            (0.0, 1.0)
+        } else if degrees == 90.0 {
+            (1.0, 0.0)
+        } else if degrees == 180.0 {
+            (0.0, -1.0)
+        } else if degrees == 270.0 {
+            (-1.0, 0.0)
+        } else {
+            degrees.to_radians().sin_cos()
+        };
+        let new_width = (width * cos.abs() + height * sin.abs()).ceil() as u32;
+        let new_height = (width * sin.abs() + height * cos.abs()).ceil() as u32;
+        let Some(mut pixmap) = Pixmap::new(new_width, new_height) else {
+            return DynamicImage::new_rgba8(0, 0);
+        };
+        let flip_scale = |flipped: bool| match flipped {
+            true => -1.0,
+            false => 1.0,
+        };
+        // Applied from the bottom up: scale, center on the origin, flip, rotate and move to the middle
+        let transform = Transform::from_translate(new_width as f32 / 2.0, new_height as f32 / 2.0)
+            .pre_rotate(degrees)
+            .pre_scale(flip_scale(flip.0), flip_scale(flip.1))
+            .pre_translate(-width / 2.0, -height / 2.0)
+            .pre_scale(scale, scale);
+        resvg::render(&svg_tree.0, transform, &mut pixmap.as_mut());
+        File::pixmap_to_image(pixmap)
+    }
+
+    pub fn flip_image(image: DynamicImage, flip: (bool, bool)) -> DynamicImage {
+        let image = match flip.0 {
+            true => image.fliph(),
+            false => image,
+        };
+        match flip.1 {
+            true => image.flipv(),
+            false => image,
+        }
+    }
+
+    // Rotates the image clockwise around its center. The canvas grows so the corners are not cut off,
+    // quarter turns are done losslessly and every other angle is resampled bilinearly
+    pub fn rotate_image(image: DynamicImage, degrees: f32) -> DynamicImage {
+        let degrees = degrees.rem_euclid(360.0);
+        if degrees == 0.0 {
+            return image;
+        } else if degrees == 90.0 {
+            return image.rotate90();
+        } else if degrees == 180.0 {
+            return image.rotate180();
+        } else if degrees == 270.0 {
+            return image.rotate270();
+        }
+        let source = image.to_rgba8();
+        let (width, height) = (source.width() as f32, source.height() as f32);
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let new_width = (width * cos.abs() + height * sin.abs()).ceil() as u32;
+        let new_height = (width * sin.abs() + height * cos.abs()).ceil() as u32;
+        let rotated = RgbaImage::from_fn(new_width, new_height, |x, y| {
+            // Map every pixel of the new image back onto the original one
+            let dx = x as f32 + 0.5 - new_width as f32 / 2.0;
+            let dy = y as f32 + 0.5 - new_height as f32 / 2.0;
+            let source_x = dx * cos + dy * sin + width / 2.0 - 0.5;
+            let source_y = -dx * sin + dy * cos + height / 2.0 - 0.5;
+            GtkTestWindow::sample_bilinear(&source, source_x, source_y)
+        });
+        DynamicImage::ImageRgba8(rotated)
+    }
+
+    // Colors are weighted by their alpha, otherwise transparent pixels bleed dark edges into the image
+    pub fn sample_bilinear(image: &RgbaImage, x: f32, y: f32) -> Rgba<u8> {
+        let x_floor = x.floor();
+        let y_floor = y.floor();
+        let (x_fraction, y_fraction) = (x - x_floor, y - y_floor);
+        let neighbours = [
+            (0, 0, (1.0 - x_fraction) * (1.0 - y_fraction)),
+            (1, 0, x_fraction * (1.0 - y_fraction)),
+            (0, 1, (1.0 - x_fraction) * y_fraction),
+            (1, 1, x_fraction * y_fraction),
+        ];
+        let mut color = [0.0f32; 3];
+        let mut alpha = 0.0f32;
+        for (offset_x, offset_y, weight) in neighbours {
+            let pixel_x = x_floor as i64 + offset_x;
+            let pixel_y = y_floor as i64 + offset_y;
+            if pixel_x < 0
+                || pixel_y < 0
+                || pixel_x >= image.width() as i64
+                || pixel_y >= image.height() as i64
+            {
+                continue;
+            }
+            let pixel = image.get_pixel(pixel_x as u32, pixel_y as u32).0;
+            let pixel_alpha = pixel[3] as f32 * weight;
+            for (channel, value) in color.iter_mut().enumerate() {
+                *value += pixel[channel] as f32 * pixel_alpha;
+            }
+            alpha += pixel_alpha;
+        }
+        if alpha <= 0.0 {
+            return Rgba([0, 0, 0, 0]);
+        }
+        Rgba([
+            (color[0] / alpha).round() as u8,
+            (color[1] / alpha).round() as u8,
+            (color[2] / alpha).round() as u8,
+            alpha.round() as u8,
+        ])
     }
 }
@@ -1,4 +1,6 @@
 pub mod alert_dialogs;
 pub mod file_handling;
+pub mod gallery;
 pub mod generation;
+pub mod perspective;
 pub mod regeneration;
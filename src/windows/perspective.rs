@@ -0,0 +1,423 @@
+use adw::{prelude::*, subclass::prelude::*};
+use gtk::glib;
+use image::*;
+use log::*;
+use std::collections::HashMap;
+
+use crate::GtkTestWindow;
+
+// Offsets of the top-left, top-right, bottom-right and bottom-left corner,
+// as a fraction of the width and height of the top image
+pub type PerspectiveCorners = [(f32, f32); 4];
+
+// In the same order as the perspective preset combo row
+const PERSPECTIVE_PRESETS: [PerspectiveCorners; 5] = [
+    // Flat
+    [(0.0, 0.0), (0.0, 0.0), (0.0, 0.0), (0.0, 0.0)],
+    // Lean back
+    [(0.1, 0.05), (-0.1, 0.05), (0.0, 0.0), (0.0, 0.0)],
+    // Lean forward
+    [(0.0, 0.0), (0.0, 0.0), (-0.1, -0.05), (0.1, -0.05)],
+    // Turn left
+    [(0.05, 0.1), (0.0, 0.0), (0.0, 0.0), (0.05, -0.1)],
+    // Turn right
+    [(0.0, 0.0), (-0.05, 0.1), (-0.05, -0.1), (0.0, 0.0)],
+];
+
+// How close (in pixels of the preview widget) the pointer has to be to grab a corner
+const HANDLE_GRAB_DISTANCE: f64 = 20.0;
+
+impl GtkTestWindow {
+    pub fn setup_perspective_handles(&self) {
+        let imp = self.imp();
+        let gesture_drag = gtk::GestureDrag::new();
+        gesture_drag.set_propagation_phase(gtk::PropagationPhase::Capture);
+        gesture_drag.connect_drag_begin(glib::clone!(
+            #[weak(rename_to = win)]
+            self,
+            move |gesture, x, y| {
+                let imp = win.imp();
+                let grabbed_corner = match imp.perspective_switch.is_active() {
+                    true => win.find_perspective_handle(x, y),
+                    false => None,
+                };
+                debug!("Grabbed perspective corner: {:?}", grabbed_corner);
+                imp.dragged_corner.replace(grabbed_corner);
+                match grabbed_corner {
+                    // Claiming the drag stops the drag source from dragging the image out of Iconic
+                    Some(_) => gesture.set_state(gtk::EventSequenceState::Claimed),
+                    None => gesture.set_state(gtk::EventSequenceState::Denied),
+                };
+            }
+        ));
+        gesture_drag.connect_drag_update(glib::clone!(
+            #[weak(rename_to = win)]
+            self,
+            move |gesture, offset_x, offset_y| {
+                let imp = win.imp();
+                let Some(corner) = *imp.dragged_corner.borrow() else {
+                    return;
+                };
+                let Some((start_x, start_y)) = gesture.start_point() else {
+                    return;
+                };
+                let Some(position) =
+                    win.widget_to_preview_position(start_x + offset_x, start_y + offset_y)
+                else {
+                    return;
+                };
+                let (left, top, width, height) = *imp.top_image_bounds.borrow();
+                if width <= 0.0 || height <= 0.0 {
+                    return;
+                }
+                let (corner_x, corner_y) = Self::rectangle_corner(corner);
+                let new_offset = (
+                    ((position.0 - left) / width - corner_x).clamp(-1.0, 1.0),
+                    ((position.1 - top) / height - corner_y).clamp(-1.0, 1.0),
+                );
+                let mut corners = *imp.perspective_corners.borrow();
+                corners[corner] = new_offset;
+                // A handle can not be dragged past the others, crossed or dented shapes can not be warped
+                if !Self::is_convex(corners) {
+                    return;
+                }
+                imp.perspective_corners.replace(corners);
+                glib::spawn_future_local(glib::clone!(
+                    #[weak]
+                    win,
+                    async move {
+                        win.render_to_screen().await;
+                        win.imp().image_saved.replace(false);
+                        win.imp().save_button.set_sensitive(true);
+                    }
+                ));
+            }
+        ));
+        gesture_drag.connect_drag_end(glib::clone!(
+            #[weak(rename_to = win)]
+            self,
+            move |_, _, _| {
+                win.imp().dragged_corner.replace(None);
+            }
+        ));
+        imp.image_view.add_controller(gesture_drag);
+    }
+
+    pub fn enable_perspective_expand(&self) {
+        let imp = self.imp();
+        imp.perspective_action_row
+            .set_property("enable_expansion", imp.perspective_switch.is_active());
+    }
+
+    pub fn perspective_preset_selected(&self) {
+        let imp = self.imp();
+        let selected_index = imp.perspective_preset.selected() as usize;
+        let Some(preset) = PERSPECTIVE_PRESETS.get(selected_index) else {
+            return;
+        };
+        imp.perspective_corners.replace(*preset);
+        if selected_index != 0 {
+            imp.perspective_switch.set_active(true);
+        }
+        let mut stored_presets: HashMap<String, String> = imp.settings.get("perspective-presets");
+        stored_presets.insert(self.bottom_style_name(), selected_index.to_string());
+        let _ = imp.settings.set("perspective-presets", stored_presets);
+        if imp.stack.visible_child_name() == Some("stack_main_page".into()) {
+            glib::spawn_future_local(glib::clone!(
+                #[weak(rename_to = win)]
+                self,
+                async move {
+                    win.render_to_screen().await;
+                    win.imp().image_saved.replace(false);
+                    win.imp().save_button.set_sensitive(true);
+                }
+            ));
+        }
+    }
+
+    /* Selects the preset that was last used with the currently loaded bottom folder style.
+    Only when the style changed, reloading the same bottom keeps corners that were dragged by hand */
+    pub fn load_perspective_preset(&self) {
+        let imp = self.imp();
+        let style_name = self.bottom_style_name();
+        if imp.perspective_style.borrow().as_ref() == Some(&style_name) {
+            return;
+        }
+        let stored_presets: HashMap<String, String> = imp.settings.get("perspective-presets");
+        let preset_index = stored_presets
+            .get(&style_name)
+            .and_then(|index| index.parse::<u32>().ok());
+        imp.perspective_style.replace(Some(style_name));
+        if let Some(preset_index) = preset_index {
+            debug!("Loading stored perspective preset {}", preset_index);
+            // Selecting the preset that is already selected does not emit a signal
+            match imp.perspective_preset.selected() == preset_index {
+                true => self.perspective_preset_selected(),
+                false => imp.perspective_preset.set_selected(preset_index),
+            }
+        }
+    }
+
+    pub fn reset_perspective(&self) {
+        let imp = self.imp();
+        imp.perspective_corners.replace(PERSPECTIVE_PRESETS[0]);
+        imp.perspective_switch.set_active(false);
+        /* Otherwise selecting the preset that was selected before the reset does nothing.
+        Not a choice of the user, so the preset stored for the style stays */
+        let handler = imp.perspective_preset_handler.borrow();
+        if let Some(handler) = handler.as_ref() {
+            imp.perspective_preset.block_signal(handler);
+        }
+        imp.perspective_preset.set_selected(0);
+        if let Some(handler) = handler.as_ref() {
+            imp.perspective_preset.unblock_signal(handler);
+        }
+    }
+
+    fn bottom_style_name(&self) -> String {
+        let imp = self.imp();
+        match imp.settings.boolean("manual-bottom-image-selection") {
+            true => imp.settings.string("folder-svg-path").into(),
+            false => String::from("built-in"),
+        }
+    }
+
+    // Returns the corner offsets that should be used to generate the image, no offsets if perspective is turned off
+    pub fn active_perspective_corners(&self) -> PerspectiveCorners {
+        let imp = self.imp();
+        match imp.perspective_switch.is_active() {
+            true => *imp.perspective_corners.borrow(),
+            false => PERSPECTIVE_PRESETS[0],
+        }
+    }
+
+    // Converts a point on the image view to a position on the preview, as a fraction of its size
+    fn widget_to_preview_position(&self, x: f64, y: f64) -> Option<(f32, f32)> {
+        let imp = self.imp();
+        let paintable = imp.image_view.paintable()?;
+        let image_width = paintable.intrinsic_width() as f64;
+        let image_height = paintable.intrinsic_height() as f64;
+        if image_width <= 0.0 || image_height <= 0.0 {
+            return None;
+        }
+        let widget_width = imp.image_view.width() as f64;
+        let widget_height = imp.image_view.height() as f64;
+        // The picture scales the preview down to fit, but never up
+        let scale = (widget_width / image_width)
+            .min(widget_height / image_height)
+            .min(1.0);
+        let margin_x = (widget_width - image_width * scale) / 2.0;
+        let margin_y = (widget_height - image_height * scale) / 2.0;
+        Some((
+            ((x - margin_x) / (image_width * scale)) as f32,
+            ((y - margin_y) / (image_height * scale)) as f32,
+        ))
+    }
+
+    fn find_perspective_handle(&self, x: f64, y: f64) -> Option<usize> {
+        let imp = self.imp();
+        let paintable = imp.image_view.paintable()?;
+        let preview_size = (
+            paintable.intrinsic_width() as f32,
+            paintable.intrinsic_height() as f32,
+        );
+        let pointer = self.widget_to_preview_position(x, y)?;
+        let widget_scale = (imp.image_view.width() as f32 / preview_size.0)
+            .min(imp.image_view.height() as f32 / preview_size.1)
+            .min(1.0);
+        self.perspective_handle_positions()
+            .iter()
+            .enumerate()
+            .map(|(index, handle)| {
+                let distance_x = (handle.0 - pointer.0) * preview_size.0 * widget_scale;
+                let distance_y = (handle.1 - pointer.1) * preview_size.1 * widget_scale;
+                (index, distance_x.hypot(distance_y))
+            })
+            .filter(|(_, distance)| (*distance as f64) < HANDLE_GRAB_DISTANCE)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(index, _)| index)
+    }
+
+    // Positions of the corner handles, as a fraction of the preview size
+    fn perspective_handle_positions(&self) -> [(f32, f32); 4] {
+        let imp = self.imp();
+        let (left, top, width, height) = *imp.top_image_bounds.borrow();
+        let corners = *imp.perspective_corners.borrow();
+        let mut positions = [(0.0, 0.0); 4];
+        for (index, position) in positions.iter_mut().enumerate() {
+            let (corner_x, corner_y) = Self::rectangle_corner(index);
+            *position = (
+                left + (corner_x + corners[index].0) * width,
+                top + (corner_y + corners[index].1) * height,
+            );
+        }
+        positions
+    }
+
+    pub fn draw_perspective_handles(&self, image: &mut DynamicImage) {
+        let (width, height) = image.dimensions();
+        let radius = (width.min(height) / 64).max(2) as i64;
+        let color = self.imp().monochrome_color.rgba();
+        let handle_color = Rgba([
+            (color.red() * 255.0) as u8,
+            (color.green() * 255.0) as u8,
+            (color.blue() * 255.0) as u8,
+            255,
+        ]);
+        for (x, y) in self.perspective_handle_positions() {
+            let center_x = (x * width as f32) as i64;
+            let center_y = (y * height as f32) as i64;
+            for pixel_y in (center_y - radius).max(0)..(center_y + radius).min(height as i64) {
+                for pixel_x in (center_x - radius).max(0)..(center_x + radius).min(width as i64) {
+                    let distance = (pixel_x - center_x).pow(2) + (pixel_y - center_y).pow(2);
+                    if distance > radius.pow(2) {
+                        continue;
+                    }
+                    // Draw a white outline so the handle stays visible on top of the folder
+                    let pixel_color = match distance > (radius - 1).pow(2) {
+                        true => Rgba([255, 255, 255, 255]),
+                        false => handle_color,
+                    };
+                    image.put_pixel(pixel_x as u32, pixel_y as u32, pixel_color);
+                }
+            }
+        }
+    }
+
+    fn rectangle_corner(index: usize) -> (f32, f32) {
+        match index {
+            0 => (0.0, 0.0),
+            1 => (1.0, 0.0),
+            2 => (1.0, 1.0),
+            _ => (0.0, 1.0),
+        }
+    }
+
+    /* Warps the image so its corners end up at the given offsets.
+    Returns the warped image, and where its top-left corner is compared to the top-left corner of the original image */
+    pub fn perspective_warp(
+        image: DynamicImage,
+        corners: PerspectiveCorners,
+    ) -> (DynamicImage, (i64, i64)) {
+        if corners == PERSPECTIVE_PRESETS[0] {
+            return (image, (0, 0));
+        }
+        let source = image.to_rgba8();
+        let (width, height) = (source.width() as f64, source.height() as f64);
+        let source_corners: [(f64, f64); 4] = std::array::from_fn(|index| {
+            let (corner_x, corner_y) = Self::rectangle_corner(index);
+            (corner_x as f64 * width, corner_y as f64 * height)
+        });
+        let target_corners: [(f64, f64); 4] = std::array::from_fn(|index| {
+            let (corner_x, corner_y) = Self::rectangle_corner(index);
+            (
+                (corner_x + corners[index].0) as f64 * width,
+                (corner_y + corners[index].1) as f64 * height,
+            )
+        });
+        if !Self::is_convex(corners) {
+            warn!("Perspective corners do not form a convex shape, not warping");
+            return (image, (0, 0));
+        }
+        // The mapping goes from the warped image back to the original, so every new pixel can be looked up
+        let Some(mut matrix) = Self::homography(target_corners, source_corners) else {
+            warn!("Perspective corners do not form a valid shape, not warping");
+            return (image, (0, 0));
+        };
+        // Scaled so w is positive inside the shape, pixels with a negative w lie beyond the horizon
+        let (first_x, first_y) = target_corners[0];
+        if matrix[6] * first_x + matrix[7] * first_y + matrix[8] < 0.0 {
+            matrix.iter_mut().for_each(|value| *value = -*value);
+        }
+        let min_x = target_corners
+            .iter()
+            .map(|c| c.0)
+            .fold(f64::MAX, f64::min)
+            .floor();
+        let min_y = target_corners
+            .iter()
+            .map(|c| c.1)
+            .fold(f64::MAX, f64::min)
+            .floor();
+        let max_x = target_corners
+            .iter()
+            .map(|c| c.0)
+            .fold(f64::MIN, f64::max)
+            .ceil();
+        let max_y = target_corners
+            .iter()
+            .map(|c| c.1)
+            .fold(f64::MIN, f64::max)
+            .ceil();
+        let warped = RgbaImage::from_fn((max_x - min_x) as u32, (max_y - min_y) as u32, |x, y| {
+            let target_x = x as f64 + 0.5 + min_x;
+            let target_y = y as f64 + 0.5 + min_y;
+            let w = matrix[6] * target_x + matrix[7] * target_y + matrix[8];
+            // Without this, the part beyond the horizon would show a mirrored copy of the image
+            if w < f64::EPSILON {
+                return Rgba([0, 0, 0, 0]);
+            }
+            let source_x = (matrix[0] * target_x + matrix[1] * target_y + matrix[2]) / w;
+            let source_y = (matrix[3] * target_x + matrix[4] * target_y + matrix[5]) / w;
+            GtkTestWindow::sample_bilinear(&source, source_x as f32 - 0.5, source_y as f32 - 0.5)
+        });
+        (
+            DynamicImage::ImageRgba8(warped),
+            (min_x as i64, min_y as i64),
+        )
+    }
+
+    // Whether the corners, after their offsets, still form a shape without crossed edges or dents
+    fn is_convex(corners: PerspectiveCorners) -> bool {
+        let points: [(f32, f32); 4] = std::array::from_fn(|index| {
+            let (corner_x, corner_y) = Self::rectangle_corner(index);
+            (corner_x + corners[index].0, corner_y + corners[index].1)
+        });
+        // The turn at every corner, they all go the same way around a convex shape
+        let turns: [f32; 4] = std::array::from_fn(|index| {
+            let (a, b, c) = (
+                points[index],
+                points[(index + 1) % 4],
+                points[(index + 2) % 4],
+            );
+            (b.0 - a.0) * (c.1 - b.1) - (b.1 - a.1) * (c.0 - b.0)
+        });
+        turns.iter().all(|turn| *turn > 0.0) || turns.iter().all(|turn| *turn < 0.0)
+    }
+
+    // Solves the projective transformation that maps the four "from" points onto the four "to" points
+    fn homography(from: [(f64, f64); 4], to: [(f64, f64); 4]) -> Option<[f64; 9]> {
+        let mut system = [[0.0f64; 9]; 8];
+        for (index, ((x, y), (u, v))) in from.into_iter().zip(to).enumerate() {
+            system[index * 2] = [x, y, 1.0, 0.0, 0.0, 0.0, -u * x, -u * y, u];
+            system[index * 2 + 1] = [0.0, 0.0, 0.0, x, y, 1.0, -v * x, -v * y, v];
+        }
+        // Gaussian elimination with partial pivoting
+        for column in 0..8 {
+            let pivot = (column..8).max_by(|a, b| {
+                system[*a][column]
+                    .abs()
+                    .total_cmp(&system[*b][column].abs())
+            })?;
+            if system[pivot][column].abs() < 1e-10 {
+                return None;
+            }
+            system.swap(column, pivot);
+            let pivot_row = system[column];
+            for (row_index, row) in system.iter_mut().enumerate() {
+                if row_index == column {
+                    continue;
+                }
+                let factor = row[column] / pivot_row[column];
+                for (entry, pivot_entry) in row.iter_mut().zip(pivot_row).skip(column) {
+                    *entry -= factor * pivot_entry;
+                }
+            }
+        }
+        let mut matrix = [1.0; 9];
+        for (index, (value, row)) in matrix.iter_mut().zip(system).enumerate() {
+            *value = row[8] / row[index];
+        }
+        Some(matrix)
+    }
+}
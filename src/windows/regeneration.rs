@@ -1,80 +1,151 @@
+use crate::objects::color_preset::ColorPreset;
+use crate::objects::color_profile;
+use crate::objects::content_hash::ContentHash;
 use crate::objects::file::File;
+use crate::objects::folder_style::FolderSource;
+use crate::objects::iconic_error::IconicError;
+use crate::objects::top_image_cache::TopImageCache;
+use crate::settings::settings::PreferencesDialog;
+use crate::windows::generation::{Monochrome, TopLayer};
+use crate::windows::perspective::PerspectiveCorners;
 use crate::{GtkTestWindow, RUNTIME};
 
 use adw::{prelude::*, subclass::prelude::*};
 use gettextrs::gettext;
 use gtk::gdk::RGBA;
-use gtk::gio;
+use gtk::{gio, glib};
 use image::*;
 use log::*;
+use std::fmt;
 use std::fs;
 use std::path::PathBuf;
+use std::str::FromStr;
 use std::time::Duration;
 
-type GenResult<T> = Result<T, Box<dyn std::error::Error>>;
-
 impl GtkTestWindow {
+    /* Keeps the top image so icons generated with it can be regenerated, or a custom bottom image when
+    is_bottom is set. The original bytes are stored when there are any, so SVGs stay SVGs. Images without
+    them, like pasted textures or dynamic images, are stored as PNG. Returns the name the image is cached
+    as, the hash of the bytes that were written, so loading the cached image again gives the same name.
+    Storing an image that is already cached only marks it as used */
     pub fn store_top_image_in_cache(
         &self,
         file: &File,
-        original_file: Option<&gio::File>,
-    ) -> GenResult<()> {
+        original_data: Option<&glib::Bytes>,
+        is_bottom: bool,
+    ) -> Result<Option<ContentHash>, IconicError> {
         let imp = self.imp();
         if !imp.settings.boolean("store-top-in-cache") {
             debug!("Top cache is disabled");
-            return Ok(());
-        }
-        if imp.settings.boolean("manual-bottom-image-selection") {
-            debug!("Non-default bottom image");
-            return Ok(());
+            return Ok(None);
         }
-        //create folder inside cache
-        let cache_path = self.get_cache_path().join("top_images");
-        if !cache_path.exists() {
-            debug!("Top icon cache file does not yet exist, creating");
-            fs::create_dir(&cache_path)?;
+        // The empty placeholder used when only the bottom image is shown
+        if file.dynamic_image.width() <= 1 {
+            return Ok(None);
         }
-        let file_name = file.hash;
-        let mut file_path = cache_path.clone();
-        file_path.push(file_name.to_string());
-        debug!("File path: {:?}", file_path);
-        debug!("File name: {:?}", file.filename);
-        match file_path.exists() {
-            true => {
-                debug!("file already exists with name");
-                return Ok(());
-            }
-            false => {
-                debug!("File does not yet exist, creating");
-                fs::File::create(&file_path)?;
+        let cache = TopImageCache::open(&self.get_cache_path());
+        fs::create_dir_all(cache.directory())?;
+        let mut hash = file.hash;
+        if !cache.entry_path(&hash.to_string()).exists() {
+            let data = match original_data {
+                Some(bytes) => bytes.to_vec(),
+                None => {
+                    let mut png = std::io::Cursor::new(vec![]);
+                    file.dynamic_image
+                        .write_to(&mut png, ImageFormat::Png)
+                        .map_err(|err| IconicError::from_image_error(err, "PNG"))?;
+                    png.into_inner()
+                }
+            };
+            hash = ContentHash::of(&data);
+            let file_name = hash.to_string();
+            let file_path = cache.entry_path(&file_name);
+            debug!("Top image {:?} cached as {:?}", file.filename, file_path);
+            if !file_path.exists() {
+                // Written to a temporary file first and then renamed, so a failed write never leaves a broken image
+                gio::File::for_path(&file_path).replace_contents(
+                    &data,
+                    None,
+                    false,
+                    gio::FileCreateFlags::REPLACE_DESTINATION,
+                    gio::Cancellable::NONE,
+                )?;
+                if let Some(perceptual_hash) = file.perceptual_hash {
+                    let duplicates = cache.near_duplicates(&file_name, perceptual_hash);
+                    if !duplicates.is_empty() {
+                        info!("Top image {} looks like cached {:?}", file_name, duplicates);
+                    }
+                }
+                cache.record(&file_name, &file.filename, file.perceptual_hash);
+                cache.set_bottom(&file_name, is_bottom);
+                let removed = cache
+                    .enforce_quota(imp.settings.int("top-cache-quota"), &Self::get_data_path());
+                if !removed.is_empty() {
+                    info!("Removed least recently used top images {:?}", removed);
+                }
             }
+        }
+        let file_name = hash.to_string();
+        cache.touch(&file_name);
+        // Once used as top image it is shown as one, even if it was cached as a bottom image first
+        if !is_bottom {
+            cache.set_bottom(&file_name, false);
+        }
+        cache.save();
+        Ok(Some(hash))
+    }
+
+    /* Caches the top image, or the bottom image when is_bottom is set, if it is not cached yet. The file
+    it was loaded from is read again for that, so the original bytes do not have to be kept in memory.
+    When it had to be stored as PNG the loaded image takes over the name of the PNG, so the file names
+    of generated icons point at the cached image */
+    pub fn ensure_cached(&self, is_bottom: bool) -> Result<(), IconicError> {
+        let imp = self.imp();
+        let mut image_file = match is_bottom {
+            true => imp.bottom_image_file.lock().unwrap(),
+            false => imp.top_image_file.lock().unwrap(),
         };
-        // SVG's are often very small in size, so if it is an SVG. Save that image. Otherwise store the dynamic image.
-        // I do not know how this code below works, but it does. So I am not touching it
-        // TODO implement async?
-        //if file.extension == "image/svg+xml" && original_file != None {
-        let new_file = gio::File::for_path(file_path);
-        let filestream = new_file.open_readwrite(gio::Cancellable::NONE).unwrap();
-        let test = filestream.output_stream();
-        let buffer = original_file
-            .unwrap()
-            .load_bytes(gio::Cancellable::NONE)
-            .unwrap();
-        test.write_bytes(&buffer.0, gio::Cancellable::NONE).unwrap();
-        //} else {
-        //     file.dynamic_image
-        //         .save_with_format(file_path, ImageFormat::Png)?;
-        // }
+        let Some(file) = image_file.as_mut() else {
+            return Ok(());
+        };
+        let is_cached = TopImageCache::open(&self.get_cache_path())
+            .entry_path(&file.hash.to_string())
+            .exists();
+        let original_data = match is_cached {
+            true => None,
+            false => Self::original_bytes(file),
+        };
+        if let Some(hash) =
+            self.store_top_image_in_cache(file, original_data.as_ref(), is_bottom)?
+        {
+            file.hash = hash;
+        }
         Ok(())
     }
 
+    // None for images that were not loaded from a file, or when the file changed since
+    fn original_bytes(file: &File) -> Option<glib::Bytes> {
+        let source = file.files.clone()?;
+        match source.load_bytes(gio::Cancellable::NONE) {
+            Ok((bytes, _)) if ContentHash::of(&bytes) == file.hash => Some(bytes),
+            Ok(_) => {
+                debug!("{} changed since it was loaded", file.filename);
+                None
+            }
+            Err(err) => {
+                debug!("Could not read {} again: {}", file.filename, err);
+                None
+            }
+        }
+    }
+
     /*
     This function regenerates icon, it replaces all images that were dragged and dropped with ones of the correct system accent color.
     It is currently incredibly slow, but it does work.
     After I added the animation, it got only more ugly. But the animation looks nice :)*/
-    pub async fn regenerate_icons(&self, delay: bool) -> GenResult<()> {
+    pub async fn regenerate_icons(&self, delay: bool) -> Result<(), IconicError> {
         let imp = self.imp();
-        let data_path = self.get_data_path();
+        let data_path = Self::get_data_path();
         let mut incompatible_files_n: u32 = 0;
         let compatible_files =
             self.find_regeneratable_icons(data_path, &mut incompatible_files_n)?;
@@ -94,6 +165,7 @@ impl GtkTestWindow {
         }
         let step_size = 1.0 / files_n as f64;
         let mut file_index: usize = 0;
+        let top_image_cache = TopImageCache::open(&self.get_cache_path());
         for file in compatible_files {
             info!("Loading new file");
 
@@ -101,36 +173,64 @@ impl GtkTestWindow {
             self.progress_animation(step_size);
             let file_name = file.file_name();
             let file_path = file.path();
-            let file_name = file_name.to_str().unwrap().to_string();
-            let file_properties = file_name.split("-");
-            let properties_list: Vec<&str> = file_properties.into_iter().collect();
+            // Names that are not UTF-8 are skipped when looking for icons to regenerate
+            let file_name = file_name.to_string_lossy().to_string();
+            let properties_list: Vec<&str> = file_name.split("-").collect();
             info!("properties list: {:?}", properties_list);
-            let current_accent_color = self.get_accent_color_and_dialog();
-            let bottom_image_path = PathBuf::from(format!(
-                "/app/share/folder_icon/folders/folder_{}.svg",
-                &current_accent_color
-            ));
-            let hash = properties_list[12].split(".").nth(0).unwrap();
-            let mut top_image_path = self.get_cache_path().join("top_images");
-            top_image_path.push(hash);
+            let accent_rgba;
+            let bottom_image_source = match (
+                self.color_preset_of(&properties_list),
+                Self::custom_bottom_of(&properties_list),
+            ) {
+                (Some(preset), _) => {
+                    info!("Using color preset {}", preset.name);
+                    accent_rgba = PreferencesDialog::hex_to_rgba(preset.secondary.clone());
+                    self.write_custom_folder(
+                        preset.primary,
+                        preset.secondary,
+                        "regeneration_folder.svg",
+                    )
+                    .await
+                    .map(FolderSource::Path)?
+                }
+                (None, Some(bottom)) => {
+                    info!("Using cached bottom image {}", bottom);
+                    accent_rgba = self.current_accent_rgba()?;
+                    top_image_cache.touch(bottom);
+                    FolderSource::Path(top_image_cache.entry_path(bottom))
+                }
+                (None, None) => {
+                    accent_rgba = self.current_accent_rgba()?;
+                    self.style_folder_for_color(
+                        &self.get_accent_color_and_dialog(),
+                        "regeneration_folder.svg",
+                    )
+                    .await?
+                }
+            };
+            let hash = Self::top_image_name(&properties_list)?;
+            let top_image_path = top_image_cache.entry_path(hash);
+            if !top_image_path.exists() {
+                return Err(IconicError::MissingCacheEntry(top_image_path));
+            }
+            top_image_cache.touch(hash);
             info!("Loading top image file");
+            let resampling = self.resampling();
             let top_image_file = RUNTIME
-                .spawn_blocking(move || {
-                    File::from_path(top_image_path, 1024, 0).map_err(|err| err.to_string())
-                })
-                .await??
-                .dynamic_image;
-            self.set_properties(properties_list.clone())?;
-            let top_image = self.create_top_image_for_generation(properties_list, top_image_file);
+                .spawn_blocking(move || File::from_path(top_image_path, 1024, 0, resampling))
+                .await
+                .unwrap()?;
+            self.set_properties(properties_list.clone(), accent_rgba)?;
+            let top_layer = TopLayer::from_file(&top_image_file, &top_image_file.dynamic_image);
+            let monochrome = self.monochrome_for_generation(properties_list, accent_rgba)?;
             info!(
                 "Creating top icon succesful, now creating bottom icon {:?}",
-                bottom_image_path
+                bottom_image_source
             );
             let bottom_image_file = RUNTIME
-                .spawn_blocking(move || {
-                    File::from_path(bottom_image_path, 1024, 0).map_err(|err| err.to_string())
-                })
-                .await??
+                .spawn_blocking(move || bottom_image_source.to_file(1024, 0, resampling))
+                .await
+                .unwrap()?
                 .dynamic_image;
             self.image_animation(false);
             if delay {
@@ -143,7 +243,7 @@ impl GtkTestWindow {
             }
             info!("Generating image");
             let generated_image = self
-                .generate_image(bottom_image_file, top_image, imageops::FilterType::Gaussian)
+                .generate_image(bottom_image_file, top_layer, monochrome, resampling)
                 .await;
             info!("Setting texture");
             let pixbuf = self.dynamic_image_to_texture(&generated_image);
@@ -154,9 +254,10 @@ impl GtkTestWindow {
             info!("Image animation");
             self.image_animation(true);
             info!("Saving image");
+            let embed_profile = imp.settings.boolean("embed-color-profile");
             match RUNTIME
                 .spawn_blocking(move || {
-                    generated_image.save_with_format(file_path, ImageFormat::Png)
+                    color_profile::save_png(&generated_image, &file_path, embed_profile)
                 })
                 .await
                 .unwrap()
@@ -174,6 +275,7 @@ impl GtkTestWindow {
                     .unwrap(); //I worked really hard on my animation but the app is too fast in production. But it is my own app and I can do what I want
             }
         }
+        top_image_cache.save();
         self.default_sliders();
         self.reset_colors();
         Ok(())
@@ -183,32 +285,44 @@ impl GtkTestWindow {
         &self,
         dir: PathBuf,
         incompatible_files: &mut u32,
-    ) -> GenResult<Vec<fs::DirEntry>> {
+    ) -> Result<Vec<fs::DirEntry>, IconicError> {
+        let top_image_cache = TopImageCache::open(&self.get_cache_path());
         let mut regeneratable: Vec<fs::DirEntry> = vec![];
-        let files: fs::ReadDir = fs::read_dir(&dir).unwrap();
+        let files: fs::ReadDir = fs::read_dir(&dir)?;
         for file in files {
-            *incompatible_files += 1;
             let current_file = file?;
+            // Directories such as folder-styles are not generated icons
+            if current_file.file_type()?.is_dir() {
+                continue;
+            }
+            *incompatible_files += 1;
             let file_name = current_file.file_name();
             debug!("File found: {:?}", file_name);
-            let file_name_str = file_name.to_str().unwrap().to_string();
-            let mut file_properties = file_name_str.split("-");
+            let Some(file_name_str) = file_name.to_str() else {
+                warn!("File name is not UTF-8, not converting");
+                continue;
+            };
+            let properties_list: Vec<&str> = file_name_str.split("-").collect();
             // imp.regeneration_progress
             //     .set_fraction(imp.regeneration_progress.fraction() + step_size);
-            if file_properties.nth(0).unwrap_or("folder") != "folder_new" {
+            if properties_list.first() != Some(&"folder_new") {
                 warn!("File not supported for regeneration");
                 continue;
             }
-            let properties_list: Vec<&str> = file_properties.into_iter().collect();
-            if !(properties_list[0].parse::<usize>().unwrap() != 0) {
-                warn!("Non-default image, not converting");
+            let is_default = Self::parse_property::<usize>(&properties_list, 1).unwrap_or(0) != 0;
+            let has_bottom = match Self::custom_bottom_of(&properties_list) {
+                Some(bottom) => top_image_cache.entry_path(bottom).exists(),
+                None => false,
+            };
+            if !is_default && self.color_preset_of(&properties_list).is_none() && !has_bottom {
+                warn!("Non-default image without a cached bottom image, not converting");
                 continue;
             }
-            let hash = properties_list[11].split(".").nth(0).unwrap();
-            let mut top_image_path = self.get_cache_path().join("top_images");
-            top_image_path.push(hash);
-
-            if !top_image_path.exists() {
+            let Ok(hash) = Self::top_image_name(&properties_list) else {
+                warn!("File has no top image name");
+                continue;
+            };
+            if !top_image_cache.entry_path(hash).exists() {
                 warn!("Top image file not found");
                 continue;
             }
@@ -218,48 +332,129 @@ impl GtkTestWindow {
         Ok(regeneratable)
     }
 
-    fn set_properties(&self, properties: Vec<&str>) -> GenResult<()> {
+    // The name of the cached top image, the last property without the extension
+    fn top_image_name<'a>(properties: &[&'a str]) -> Result<&'a str, IconicError> {
+        properties
+            .last()
+            .and_then(|last| last.split('.').next())
+            .filter(|name| !name.is_empty())
+            .ok_or_else(|| IconicError::RegenerationParse {
+                file_name: properties.join("-"),
+                reason: String::from("the top image name is missing"),
+            })
+    }
+
+    // Icons generated with a color preset store its id right before the hash
+    fn color_preset_of(&self, properties: &[&str]) -> Option<ColorPreset> {
+        match properties.len() >= 25 {
+            true => ColorPreset::find(&self.imp().settings, properties[23]),
+            false => None,
+        }
+    }
+
+    // Icons generated with a custom bottom image store its name between the color preset and the hash
+    fn custom_bottom_of<'a>(properties: &[&'a str]) -> Option<&'a str> {
+        match properties.len() >= 26 {
+            true => Some(properties[24]).filter(|bottom| *bottom != "none"),
+            false => None,
+        }
+    }
+
+    // Reads one of the properties stored in the file name of a generated icon
+    pub fn parse_property<T>(properties: &[&str], index: usize) -> Result<T, IconicError>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        let parse_error = |reason: String| IconicError::RegenerationParse {
+            file_name: properties.join("-"),
+            reason,
+        };
+        let value = properties
+            .get(index)
+            .ok_or_else(|| parse_error(format!("property {} is missing", index)))?;
+        value
+            .parse()
+            .map_err(|err| parse_error(format!("property {} \"{}\": {}", index, value, err)))
+    }
+
+    pub fn set_properties(
+        &self,
+        properties: Vec<&str>,
+        accent_rgba: RGBA,
+    ) -> Result<(), IconicError> {
         let imp = self.imp();
-        imp.x_scale.set_value(properties[2].parse()?);
-        imp.y_scale.set_value(properties[3].parse()?);
-        imp.size.set_value(properties[4].parse()?);
+        imp.x_scale.set_value(Self::parse_property(&properties, 2)?);
+        imp.y_scale.set_value(Self::parse_property(&properties, 3)?);
+        imp.size.set_value(Self::parse_property(&properties, 4)?);
         imp.monochrome_switch
-            .set_active(properties[5].parse::<usize>()? != 0);
-        imp.threshold_scale.set_value(properties[6].parse()?);
-        imp.monochrome_color.set_rgba(&self.current_accent_rgba());
+            .set_active(Self::parse_property::<usize>(&properties, 5)? != 0);
+        imp.threshold_scale
+            .set_value(Self::parse_property(&properties, 6)?);
+        imp.monochrome_color.set_rgba(&accent_rgba);
         imp.monochrome_invert
-            .set_active(properties[10].parse::<usize>()? != 0);
+            .set_active(Self::parse_property::<usize>(&properties, 10)? != 0);
+        // Icons generated before rotation was added do not store the transform
+        let (rotation, flip_horizontal, flip_vertical) = match properties.len() >= 16 {
+            true => (
+                Self::parse_property::<f64>(&properties, 12)?,
+                Self::parse_property::<usize>(&properties, 13)? != 0,
+                Self::parse_property::<usize>(&properties, 14)? != 0,
+            ),
+            false => (0.0, false, false),
+        };
+        imp.rotation_scale
+            .set_value((rotation + 180.0).rem_euclid(360.0) - 180.0);
+        imp.flip_horizontal_button.set_active(flip_horizontal);
+        imp.flip_vertical_button.set_active(flip_vertical);
+        let mut corners: PerspectiveCorners = [(0.0, 0.0); 4];
+        if properties.len() >= 24 {
+            for (index, corner) in corners.iter_mut().enumerate() {
+                *corner = (
+                    Self::parse_property::<f32>(&properties, 15 + index * 2)? - 1.0,
+                    Self::parse_property::<f32>(&properties, 16 + index * 2)? - 1.0,
+                );
+            }
+        }
+        imp.perspective_corners.replace(corners);
+        imp.perspective_switch
+            .set_active(corners.iter().any(|corner| *corner != (0.0, 0.0)));
         Ok(())
     }
 
-    fn create_top_image_for_generation(
+    fn monochrome_for_generation(
         &self,
         properties: Vec<&str>,
-        top_image: DynamicImage,
-    ) -> DynamicImage {
-        let color = match properties[10] {
-            "false" => RGBA::new(
-                properties[7].parse().unwrap(),
-                properties[8].parse().unwrap(),
-                properties[9].parse().unwrap(),
+        accent_rgba: RGBA,
+    ) -> Result<Option<Monochrome>, IconicError> {
+        // Index 11 tells whether the monochrome color followed the accent color, like the gallery reads it
+        let color = match properties.get(11) {
+            Some(&"false") => RGBA::new(
+                Self::parse_property(&properties, 7)?,
+                Self::parse_property(&properties, 8)?,
+                Self::parse_property(&properties, 9)?,
                 1.0,
             ),
-            _ => self.current_accent_rgba(),
+            _ => accent_rgba,
         };
-        match properties[5] {
-            "1" => self.to_monochrome(top_image, properties[6].parse().unwrap(), color),
-            _ => top_image,
-        }
+        Ok(match properties.get(5) {
+            Some(&"1") => Some(Monochrome {
+                threshold: Self::parse_property(&properties, 6)?,
+                color,
+                invert: self.imp().monochrome_invert.is_active(),
+            }),
+            _ => None,
+        })
     }
 
-    fn current_accent_rgba(&self) -> RGBA {
+    fn current_accent_rgba(&self) -> Result<RGBA, IconicError> {
         let imp = self.imp();
         let accent_color = self.get_accent_color_and_dialog();
         imp.default_color
             .borrow()
             .get(&accent_color)
-            .unwrap()
-            .clone()
+            .cloned()
+            .ok_or(IconicError::UnknownAccentColor(accent_color))
     }
 
     fn progress_animation(&self, step_size: f64) {
@@ -0,0 +1,204 @@
+/* Reference-image tests for the alpha handling of rendering, run with `cargo test`.
+Anti-aliased edges are where premultiplied alpha and gamma mistakes show up, so these render SVG edges
+and put them on a folder colored background. Set ICONIC_UPDATE_REFERENCES=1 to write new reference images
+after an intended change, and check them by eye before committing them */
+#[path = "../src/objects/pixels.rs"]
+mod pixels;
+
+use image::{ImageFormat, Rgba, RgbaImage};
+use resvg::tiny_skia::{Pixmap, Transform};
+use resvg::usvg::{Options, Tree};
+use std::path::PathBuf;
+
+const FOLDER_BLUE: Rgba<u8> = Rgba([67, 141, 230, 255]);
+// A white circle and a red stroke, most of their pixels are edges at this size
+const EDGE_SVG: &str = r##"<svg xmlns="http://www.w3.org/2000/svg" width="32" height="32">
+<circle cx="16" cy="16" r="9.3" fill="#ffffff"/>
+<path d="M 2 30 L 30 27" stroke="#e01b24" stroke-width="1.5"/>
+</svg>"##;
+
+fn render(svg: &str, size: u32) -> RgbaImage {
+    let tree = Tree::from_str(svg, &Options::default()).unwrap();
+    let mut pixmap = Pixmap::new(size, size).unwrap();
+    let scale = size as f32 / tree.size().width();
+    resvg::render(
+        &tree,
+        Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+    pixels::pixmap_to_rgba(pixmap)
+}
+
+fn assert_matches_reference(image: &RgbaImage, name: &str) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/reference")
+        .join(format!("{}.png", name));
+    if std::env::var("ICONIC_UPDATE_REFERENCES").is_ok() {
+        image.save_with_format(&path, ImageFormat::Png).unwrap();
+        return;
+    }
+    let reference = image::open(&path)
+        .unwrap_or_else(|err| panic!("Could not open reference {:?}: {}", path, err))
+        .into_rgba8();
+    assert_eq!(image.dimensions(), reference.dimensions());
+    for (x, y, pixel) in image.enumerate_pixels() {
+        let expected = reference.get_pixel(x, y);
+        // One step of difference is allowed for rounding in other versions of resvg
+        let close = pixel
+            .0
+            .iter()
+            .zip(expected.0.iter())
+            .all(|(value, expected)| value.abs_diff(*expected) <= 1);
+        assert!(
+            close,
+            "{} differs at ({}, {}): {:?}, expected {:?}",
+            name, x, y, pixel, expected
+        );
+    }
+}
+
+#[test]
+fn edges_keep_their_color() {
+    let image = render(EDGE_SVG, 32);
+    let circle_edge = image
+        .pixels()
+        .filter(|pixel| pixel[3] > 0 && pixel[3] < 255 && pixel[1] > 200)
+        .count();
+    assert!(circle_edge > 0, "The circle has no anti-aliased edge");
+    // Semi-transparent pixels of the white circle are white, only their alpha is lower
+    for pixel in image
+        .pixels()
+        .filter(|pixel| pixel[3] > 0 && pixel[1] > 200)
+    {
+        assert!(
+            pixel[0] >= 254 && pixel[1] >= 254 && pixel[2] >= 254,
+            "Edge pixel turned dark: {:?}",
+            pixel
+        );
+    }
+}
+
+#[test]
+fn demultiply_restores_straight_alpha() {
+    let mut transparent = [10, 20, 30, 0];
+    pixels::demultiply(&mut transparent);
+    assert_eq!(transparent, [0, 0, 0, 0]);
+    let mut opaque = [10, 20, 30, 255];
+    pixels::demultiply(&mut opaque);
+    assert_eq!(opaque, [10, 20, 30, 255]);
+    // 50% white is stored as 128 in every channel
+    let mut half_white = [128, 128, 128, 128];
+    pixels::demultiply(&mut half_white);
+    assert_eq!(half_white, [255, 255, 255, 128]);
+}
+
+#[test]
+fn blending_happens_in_linear_light() {
+    // Half white over black is half the light, which is 188 in sRGB and not 128
+    let mut base = RgbaImage::from_pixel(1, 1, Rgba([0, 0, 0, 255]));
+    let top = RgbaImage::from_pixel(1, 1, Rgba([255, 255, 255, 128]));
+    pixels::overlay_linear(&mut base, &top, 0, 0);
+    let pixel = base.get_pixel(0, 0);
+    assert!(pixel[0].abs_diff(188) <= 1, "Got {:?}", pixel);
+    assert_eq!(pixel[3], 255);
+}
+
+#[test]
+fn blending_keeps_transparent_base() {
+    // Over nothing the top pixel stays the same, instead of being mixed with black
+    let mut base = RgbaImage::from_pixel(1, 1, Rgba([0, 0, 0, 0]));
+    let top = RgbaImage::from_pixel(1, 1, Rgba([224, 27, 36, 100]));
+    pixels::overlay_linear(&mut base, &top, 0, 0);
+    let pixel = base.get_pixel(0, 0);
+    for channel in 0..4 {
+        assert!(
+            pixel[channel].abs_diff(top.get_pixel(0, 0)[channel]) <= 1,
+            "Got {:?}",
+            pixel
+        );
+    }
+}
+
+#[test]
+fn overlay_is_clipped_to_the_base() {
+    let mut base = RgbaImage::from_pixel(4, 4, FOLDER_BLUE);
+    let top = RgbaImage::from_pixel(4, 4, Rgba([255, 255, 255, 255]));
+    pixels::overlay_linear(&mut base, &top, -2, 3);
+    for (x, y, pixel) in base.enumerate_pixels() {
+        let expected = match x < 2 && y == 3 {
+            true => Rgba([255, 255, 255, 255]),
+            false => FOLDER_BLUE,
+        };
+        assert_eq!(*pixel, expected, "at ({}, {})", x, y);
+    }
+}
+
+// Whether monochrome keeps the pixel, for every threshold in the list
+fn monochrome_keeps(pixel: Rgba<u8>, thresholds: &[u8]) -> Vec<bool> {
+    thresholds
+        .iter()
+        .map(|threshold| {
+            let image = RgbaImage::from_pixel(1, 1, pixel);
+            pixels::monochrome(image, *threshold, [255, 255, 255], false).get_pixel(0, 0)[3] > 0
+        })
+        .collect()
+}
+
+#[test]
+fn monochrome_thresholds_grays_by_their_value() {
+    assert_eq!(
+        monochrome_keeps(Rgba([130, 130, 130, 255]), &[125, 135]),
+        [true, false]
+    );
+    assert_eq!(
+        monochrome_keeps(Rgba([30, 30, 30, 255]), &[25, 35]),
+        [true, false]
+    );
+}
+
+#[test]
+fn monochrome_thresholds_colors_on_linear_luminance() {
+    // Pure green is much brighter than pure blue, red is in between at about half the light
+    assert_eq!(
+        monochrome_keeps(Rgba([0, 255, 0, 255]), &[210, 230]),
+        [true, false]
+    );
+    assert_eq!(
+        monochrome_keeps(Rgba([255, 0, 0, 255]), &[120, 135]),
+        [true, false]
+    );
+    assert_eq!(
+        monochrome_keeps(Rgba([0, 0, 255, 255]), &[70, 85]),
+        [true, false]
+    );
+}
+
+#[test]
+fn monochrome_colors_kept_pixels_and_clears_the_rest() {
+    let mut image = RgbaImage::from_pixel(3, 1, Rgba([255, 255, 255, 255]));
+    image.put_pixel(1, 0, Rgba([0, 0, 0, 255]));
+    image.put_pixel(2, 0, Rgba([255, 255, 255, 0]));
+    let color = [224, 27, 36];
+    let result = pixels::monochrome(image.clone(), 128, color, false);
+    assert_eq!(*result.get_pixel(0, 0), Rgba([224, 27, 36, 255]));
+    assert_eq!(*result.get_pixel(1, 0), Rgba([0, 0, 0, 0]));
+    assert_eq!(*result.get_pixel(2, 0), Rgba([0, 0, 0, 0]));
+    // Inverting swaps the kept and cleared pixels, transparent ones get the color but stay transparent
+    let inverted = pixels::monochrome(image, 128, color, true);
+    assert_eq!(*inverted.get_pixel(0, 0), Rgba([0, 0, 0, 0]));
+    assert_eq!(*inverted.get_pixel(1, 0), Rgba([224, 27, 36, 255]));
+    assert_eq!(inverted.get_pixel(2, 0).0, [224, 27, 36, 0]);
+}
+
+#[test]
+fn anti_aliased_edges_on_folder() {
+    let top = render(EDGE_SVG, 32);
+    let mut base = RgbaImage::from_pixel(40, 40, FOLDER_BLUE);
+    pixels::overlay_linear(&mut base, &top, 4, 4);
+    assert_matches_reference(&base, "anti_aliased_edges_on_folder");
+}
+
+#[test]
+fn anti_aliased_edges() {
+    assert_matches_reference(&render(EDGE_SVG, 32), "anti_aliased_edges");
+}